@@ -8,6 +8,12 @@ pub struct AABB {
     pub max: DVec3,
 }
 
+impl Default for AABB {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl AABB {
     pub fn new() -> Self {
         Self {
@@ -6,6 +6,7 @@ use crate::{
     aabb::AABB,
     hit::{Hit, HitRecord},
     ray::{Interval, Ray},
+    stats::{self, Counters},
 };
 
 #[derive(Clone, Copy, PartialEq)]
@@ -40,6 +41,21 @@ pub struct BVH {
     primitives: Vec<Box<dyn Hit>>,
 }
 
+/// Nearest hit on a primitive that isn't cut out by the alpha of its
+/// material. Whether a hit is cut out is random but fixed for the ray and
+/// the distance along it, so that a primitive split between several leaves
+/// is cut out in all of them or none.
+fn hit_opaque<'a>(primitive: &'a dyn Hit, r: &Ray, mut ray_t: Interval) -> Option<HitRecord<'a>> {
+    loop {
+        let hr = primitive.hit(r, ray_t)?;
+        let alpha = hr.material.alpha(&hr);
+        if alpha >= 1.0 || (alpha > 0.0 && r.random(hr.t.to_bits()) < alpha) {
+            return Some(hr);
+        }
+        ray_t.min = hr.t;
+    }
+}
+
 impl BVH {
     pub fn new(primitives: impl IntoIterator<Item = Box<dyn Hit>>) -> Self {
         let primitives: Vec<_> = primitives.into_iter().collect();
@@ -51,10 +67,15 @@ impl BVH {
         if self.nodes.is_empty() {
             return None;
         }
+        let mut counters = Counters {
+            rays: 1,
+            ..Counters::default()
+        };
         let mut stack = vec![0];
         let mut best_hr = None;
         while let Some(i) = stack.pop() {
             let node = &self.nodes[i];
+            counters.node_visits += 1;
             if node.aabb().ray_intersection(r, ray_t).is_none() {
                 continue;
             }
@@ -68,10 +89,11 @@ impl BVH {
                     });
                 }
                 Node::Leaf { indices, .. } => {
+                    counters.primitive_tests += indices.len() as u64;
                     best_hr = indices
                         .iter()
                         .filter_map(|&i| {
-                            let hr = self.primitives[i].hit(r, ray_t)?;
+                            let hr = hit_opaque(&*self.primitives[i], r, ray_t)?;
                             ray_t.max = hr.t;
                             Some(hr)
                         })
@@ -80,6 +102,7 @@ impl BVH {
                 }
             }
         }
+        stats::record(counters);
         best_hr
     }
 }
@@ -1,29 +1,46 @@
-use std::{collections::HashMap, time::Instant};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 
-use glam::DVec3;
-use indicatif::*;
+use glam::{DVec3, DVec4};
 
 use crate::{
-    bvh::BVH,
+    medium::MediumEvent,
+    progress::{PassStats, RenderInfo, RenderObserver, RenderStats, TerminalProgress, TileStats},
     ray::{Interval, Ray},
+    scene::Scene,
+    spectrum::Wavelengths,
+    stats::{self, Counters},
     util::default_struct,
     vector,
 };
 use itertools::Itertools;
-use rayon::iter::{IntoParallelIterator, ParallelBridge, ParallelIterator};
-
-default_struct!(Config {
-    aspect_ratio: f64 = 16.0 / 9.0,
-    image_width: u32 = 400,
-    samples_per_pixel: usize = 10,
-    max_depth: usize = 10,
-    vfov: f64 = 90.0,
-    lookfrom: DVec3 = DVec3::NEG_Z,
-    lookat: DVec3 = DVec3::ZERO,
-    vup: DVec3 = DVec3::Y,
-    defocus_angle: f64 = 0.0,
-    focus_dist: f64 = 10.0,
-});
+use rand::random;
+use rayon::iter::{IntoParallelRefMutIterator, ParallelIterator};
+
+default_struct!(
+    #[derive(Copy)]
+    Config {
+        aspect_ratio: f64 = 16.0 / 9.0,
+        image_width: u32 = 400,
+        samples_per_pixel: usize = 10,
+        samples_per_pass: usize = 8,
+        time_budget: Option<Duration> = None,
+        target_noise: Option<f64> = None,
+        max_depth: usize = 10,
+        spectral: bool = false,
+        vfov: f64 = 90.0,
+        lookfrom: DVec3 = DVec3::NEG_Z,
+        lookat: DVec3 = DVec3::ZERO,
+        vup: DVec3 = DVec3::Y,
+        defocus_angle: f64 = 0.0,
+        focus_dist: f64 = 10.0,
+    }
+);
 
 pub struct Camera {
     config: Config,
@@ -34,6 +51,72 @@ pub struct Camera {
     pixel_delta_v: DVec3,
     defocus_disk_u: DVec3,
     defocus_disk_v: DVec3,
+    cancellation_token: CancellationToken,
+}
+
+/// Shared flag used to stop a render early. The render finishes the pass in
+/// progress and then writes out the image accumulated so far.
+#[derive(Clone, Default, Debug)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+const TILE_SIZE: u32 = 16;
+
+/// Scattering events inside media along a path after which it may be ended
+/// by Russian roulette, in proportion to how little light it still carries.
+const ROULETTE_SCATTER_EVENTS: usize = 16;
+
+/// Limit on scattering events inside media along a path, for random walks in
+/// dense media that scatter almost all light and so survive the roulette.
+/// Paths cut short lose energy, and are counted in `Counters`.
+pub const MAX_SCATTER_EVENTS: usize = 1024;
+
+/// Rectangle of the image rendered as one unit of work.
+struct Tile {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    pixels: Vec<Pixel>,
+}
+
+/// Running sums of the samples taken for one pixel.
+#[derive(Clone, Copy, Default)]
+struct Pixel {
+    colour: DVec3,
+    luminance: f64,
+    luminance_sq: f64,
+}
+
+impl Pixel {
+    fn add(&mut self, colour: DVec3) {
+        let luminance = vector::luminance(colour);
+        self.colour += colour;
+        self.luminance += luminance;
+        self.luminance_sq += luminance * luminance;
+    }
+
+    fn relative_error(&self, samples: usize) -> f64 {
+        // Offset the mean so that near-black pixels don't dominate
+        const DARK_OFFSET: f64 = 1e-2;
+        let n = samples as f64;
+        let mean = self.luminance / n;
+        let variance = (self.luminance_sq / n - mean * mean).max(0.0) / (n - 1.0);
+        variance.sqrt() / (mean + DARK_OFFSET)
+    }
 }
 
 impl Config {
@@ -78,6 +161,7 @@ impl Config {
             pixel_delta_v,
             defocus_disk_u,
             defocus_disk_v,
+            cancellation_token: CancellationToken::new(),
         }
     }
 }
@@ -95,20 +179,133 @@ fn write_colour(colour: DVec3, samples_per_pixel: usize) {
 }
 
 impl Camera {
-    pub fn ray_colour(&self, bvh: &BVH, r: &Ray, depth: usize) -> DVec3 {
-        if depth == 0 {
-            return DVec3::ZERO;
+    /// Colour seen along a camera ray. In spectral mode throughput and
+    /// radiance are carried at the ray's wavelengths, with the colours of
+    /// surfaces, media and lights converted to spectra where they are met,
+    /// and the radiance is converted to a film colour using the wavelengths
+    /// the path ended with.
+    pub fn ray_colour(&self, scene: &Scene, r: &Ray, depth: usize) -> DVec3 {
+        let mut r = *r;
+        let mut throughput = DVec4::ONE;
+        let mut radiance = DVec4::ZERO;
+        // Medium the ray is travelling through. Media don't nest, so leaving
+        // an object returns the ray to the atmosphere.
+        let mut medium = scene.medium();
+        // Density with which the direction of the ray was sampled, to weigh
+        // light it reaches against light sampling. None for camera rays and
+        // specular scattering.
+        let mut scatter_pdf = None;
+        // Point where the ray was scattered and the normal there, or zero in
+        // media, for choosing lights in the same way as light sampling did
+        // there. Passing through the boundaries of media moves the ray on
+        // but leaves these alone.
+        let mut origin = r.origin;
+        let mut normal = DVec3::ZERO;
+        let mut bounces = 0;
+        let mut scatter_events = 0;
+        while bounces < depth {
+            let hit = scene.hit(&r, Interval::new(1e-3, f64::INFINITY));
+            if let Some(medium) = medium {
+                let t_max = hit.map_or(f64::INFINITY, |hr| hr.t);
+                match medium.sample(&r, t_max) {
+                    MediumEvent::Scatter {
+                        t,
+                        weight,
+                        emission,
+                        phase,
+                    } => {
+                        radiance += throughput * r.spectrum(emission);
+                        // Random walks take many more steps than surface
+                        // paths, so they have their own limits
+                        scatter_events += 1;
+                        if scatter_events > MAX_SCATTER_EVENTS {
+                            stats::record(Counters {
+                                truncated_paths: 1,
+                                ..Counters::default()
+                            });
+                            break;
+                        }
+                        throughput *= r.spectrum(weight);
+                        if scatter_events > ROULETTE_SCATTER_EVENTS {
+                            let survival = throughput.max_element().min(1.0);
+                            if random::<f64>() >= survival {
+                                break;
+                            }
+                            throughput /= survival;
+                        }
+                        let (p, direction) = (r.at(t), r.direction);
+                        radiance += throughput
+                            * scene.direct_light(p, DVec3::ZERO, r.wavelengths, |wi| {
+                                let value = phase.p(direction.dot(wi));
+                                (DVec3::splat(value), value, Some(medium))
+                            });
+                        let wi = phase.sample(direction);
+                        scatter_pdf = Some(phase.p(direction.dot(wi)));
+                        origin = p;
+                        normal = DVec3::ZERO;
+                        r = Ray::new(p, wi).with_wavelengths(r.wavelengths);
+                        continue;
+                    }
+                    MediumEvent::Pass { weight, emission } => {
+                        radiance += throughput * r.spectrum(emission);
+                        throughput *= r.spectrum(weight);
+                    }
+                }
+            }
+            let Some(hr) = hit else {
+                radiance += throughput * scene.background(&r, scatter_pdf);
+                break;
+            };
+            if hr.material.is_interface() {
+                // Boundaries of media neither scatter nor count as bounces
+                medium = if hr.front_face {
+                    hr.material.interior()
+                } else {
+                    scene.medium()
+                };
+                r = hr.ray(r.direction).with_wavelengths(r.wavelengths);
+                continue;
+            }
+            radiance += throughput * scene.emission(&r, &hr, origin, normal, scatter_pdf);
+            bounces += 1;
+            if hr.material.dispersive() {
+                if let Some(wavelengths) = &mut r.wavelengths {
+                    wavelengths.terminate_secondary();
+                }
+            }
+            let wo = -r.direction;
+            let outside = medium;
+            radiance += throughput
+                * scene.direct_light(hr.p, hr.normal, r.wavelengths, |wi| {
+                    // Light through the surface travels through the medium
+                    // on the other side
+                    let medium = if wi.dot(hr.geometric_normal) > 0.0 {
+                        outside
+                    } else if hr.front_face {
+                        hr.material.interior()
+                    } else {
+                        scene.medium()
+                    };
+                    let value = hr.material.eval(&hr, wi, wo);
+                    (value, hr.material.pdf(&hr, wi, wo), medium)
+                });
+            let Some(sample) = hr.material.sample(&r, &hr) else {
+                break;
+            };
+            if sample.lobe.is_transmission() {
+                medium = if hr.front_face {
+                    hr.material.interior()
+                } else {
+                    scene.medium()
+                };
+            }
+            scatter_pdf = (!sample.lobe.is_specular()).then_some(sample.pdf);
+            origin = hr.p;
+            normal = hr.normal;
+            throughput *= r.spectrum(sample.weight());
+            r = hr.ray(sample.wi).with_wavelengths(r.wavelengths);
         }
-        let Some(hr) = bvh.hit(r, Interval::new(1e-3, f64::INFINITY)) else {
-            // Blue sky
-            let unit_direction = r.direction.normalize();
-            let a = (unit_direction.y + 1.0) / 2.0;
-            return (1.0 - a) * DVec3::ONE + a * DVec3::new(0.5, 0.7, 1.0);
-        };
-        let Some((attenuation, scattered)) = hr.material.scatter(r, &hr) else {
-            return DVec3::ZERO;
-        };
-        attenuation * self.ray_colour(bvh, &scattered, depth - 1)
+        r.film_colour(radiance)
     }
 
     fn pixel_sample_square(&self) -> DVec3 {
@@ -132,35 +329,158 @@ impl Camera {
             self.center + p.x * self.defocus_disk_u + p.y * self.defocus_disk_v
         };
         let ray_direction = pixel_sample - ray_origin;
-        Ray::new(ray_origin, ray_direction)
+        let wavelengths = self.config.spectral.then(Wavelengths::sample);
+        Ray::new(ray_origin, ray_direction).with_wavelengths(wavelengths)
     }
 
-    pub fn render(&self, bvh: &BVH) {
-        println!("P3\n{} {}\n255", self.config.image_width, self.image_height);
-        let start = Instant::now();
-        let image: HashMap<_, _> = (0..self.image_height)
-            .cartesian_product(0..self.config.image_width)
-            .par_bridge()
-            .map(move |(j, i)| {
-                let colour = (0..self.config.samples_per_pixel)
-                    .into_par_iter()
-                    .map(|_| {
-                        let r = self.get_ray(i, j);
-                        self.ray_colour(bvh, &r, self.config.max_depth)
-                    })
-                    .reduce(|| DVec3::ZERO, |a, b| a + b);
-                ((i, j), colour)
+    /// Token which stops this camera's render early when cancelled.
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.cancellation_token.clone()
+    }
+
+    fn tiles(&self) -> Vec<Tile> {
+        let (width, height) = (self.config.image_width, self.image_height);
+        (0..height)
+            .step_by(TILE_SIZE as usize)
+            .cartesian_product((0..width).step_by(TILE_SIZE as usize))
+            .map(|(y, x)| {
+                let (width, height) = (TILE_SIZE.min(width - x), TILE_SIZE.min(height - y));
+                Tile {
+                    x,
+                    y,
+                    width,
+                    height,
+                    pixels: vec![Pixel::default(); (width * height) as usize],
+                }
             })
-            .progress_count((self.image_height * self.config.image_width) as u64)
-            .collect();
-        eprintln!("Completed in {:.3} seconds", start.elapsed().as_secs_f32());
+            .collect()
+    }
+
+    fn render_tile(&self, scene: &Scene, tile: &mut Tile, samples: usize) -> Counters {
+        let before = stats::snapshot();
+        for (index, pixel) in tile.pixels.iter_mut().enumerate() {
+            let i = tile.x + index as u32 % tile.width;
+            let j = tile.y + index as u32 / tile.width;
+            for _ in 0..samples {
+                let r = self.get_ray(i, j);
+                pixel.add(self.ray_colour(scene, &r, self.config.max_depth));
+            }
+        }
+        stats::snapshot() - before
+    }
+
+    /// Mean relative standard error of the pixel luminances.
+    fn noise(tiles: &[Tile], samples: usize) -> f64 {
+        if samples < 2 {
+            return f64::INFINITY;
+        }
+        let pixels = tiles.iter().flat_map(|t| &t.pixels);
+        let (total, count) = pixels.fold((0.0, 0), |(total, count), p| {
+            (total + p.relative_error(samples), count + 1)
+        });
+        total / count as f64
+    }
+
+    fn finished(&self, start: Instant, pass_time: Duration, pass: &PassStats) -> bool {
+        if pass.samples_per_pixel >= self.config.samples_per_pixel
+            || self.cancellation_token.is_cancelled()
+        {
+            return true;
+        }
+        // Stop if another pass won't fit in the remaining time
+        if let Some(budget) = self.config.time_budget {
+            if start.elapsed() + pass_time > budget {
+                return true;
+            }
+        }
+        if let Some(target) = self.config.target_noise {
+            if pass.noise <= target {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Render with a progress bar on stderr. See `render_with_observer`.
+    pub fn render(&self, scene: &Scene) {
+        self.render_with_observer(scene, &TerminalProgress::new());
+    }
+
+    /// Render progressively in passes of `samples_per_pass` samples per pixel
+    /// until `samples_per_pixel` is reached, the time budget runs out, the
+    /// target noise level is met or the render is cancelled. The image is
+    /// written to stdout in the PPM format.
+    pub fn render_with_observer(
+        &self,
+        scene: &Scene,
+        observer: &dyn RenderObserver,
+    ) -> RenderStats {
+        let start = Instant::now();
+        let mut tiles = self.tiles();
+        let samples_per_pass = self.config.samples_per_pass.max(1);
+        observer.render_started(&RenderInfo {
+            image_width: self.config.image_width,
+            image_height: self.image_height,
+            max_passes: self.config.samples_per_pixel.div_ceil(samples_per_pass),
+            tiles: tiles.len(),
+        });
+        let mut counters = Counters::default();
+        let mut pass = 0;
+        let mut samples = 0;
+        let noise = loop {
+            let pass_start = Instant::now();
+            let pass_samples = samples_per_pass.min(self.config.samples_per_pixel - samples);
+            counters += tiles
+                .par_iter_mut()
+                .map(|tile| {
+                    let counters = self.render_tile(scene, tile, pass_samples);
+                    observer.tile_completed(&TileStats {
+                        pass,
+                        x: tile.x,
+                        y: tile.y,
+                        width: tile.width,
+                        height: tile.height,
+                        counters,
+                    });
+                    counters
+                })
+                .reduce(Counters::default, |a, b| a + b);
+            samples += pass_samples;
+            let stats = PassStats {
+                pass,
+                samples_per_pixel: samples,
+                elapsed: start.elapsed(),
+                noise: Self::noise(&tiles, samples),
+                counters,
+            };
+            observer.pass_completed(&stats);
+            pass += 1;
+            if self.finished(start, pass_start.elapsed(), &stats) {
+                break stats.noise;
+            }
+        };
+        let stats = RenderStats {
+            passes: pass,
+            samples_per_pixel: samples,
+            elapsed: start.elapsed(),
+            noise,
+            counters,
+        };
+        observer.render_completed(&stats);
+
+        println!("P3\n{} {}\n255", self.config.image_width, self.image_height);
         for j in 0..self.image_height {
             for i in 0..self.config.image_width {
-                write_colour(
-                    *image.get(&(i, j)).expect("there is a pixel"),
-                    self.config.samples_per_pixel,
-                );
+                let tile = &tiles[self.tile_index(i, j)];
+                let pixel = tile.pixels[((j - tile.y) * tile.width + i - tile.x) as usize];
+                write_colour(pixel.colour, samples);
             }
         }
+        stats
+    }
+
+    fn tile_index(&self, i: u32, j: u32) -> usize {
+        let tiles_per_row = self.config.image_width.div_ceil(TILE_SIZE);
+        ((j / TILE_SIZE) * tiles_per_row + i / TILE_SIZE) as usize
     }
 }
@@ -0,0 +1,102 @@
+use std::sync::Arc;
+
+use glam::DVec3;
+
+use crate::{
+    hit::HitRecord,
+    material::{BsdfSample, Material},
+    medium::Medium,
+    ray::Ray,
+    texture::TextureRef,
+};
+
+/// Masks a material with an opacity texture, for foliage, fences and other
+/// surfaces modelled with holes. Rays pass through the transparent parts
+/// during traversal, without scattering.
+pub struct Cutout {
+    material: Arc<dyn Material>,
+    alpha: TextureRef,
+    threshold: Option<f64>,
+}
+
+impl Cutout {
+    pub fn new(material: Arc<dyn Material>, alpha: impl Into<TextureRef>) -> Self {
+        Self {
+            material,
+            alpha: alpha.into(),
+            threshold: None,
+        }
+    }
+
+    /// Make the surface fully opaque where alpha is at least `threshold` and
+    /// fully transparent elsewhere, rather than stochastically transparent.
+    pub fn threshold(self, threshold: f64) -> Self {
+        Self {
+            threshold: Some(threshold),
+            ..self
+        }
+    }
+}
+
+impl Material for Cutout {
+    fn sample(&self, r: &Ray, hr: &HitRecord) -> Option<BsdfSample> {
+        self.material.sample(r, hr)
+    }
+
+    fn eval(&self, hr: &HitRecord, wi: DVec3, wo: DVec3) -> DVec3 {
+        self.material.eval(hr, wi, wo)
+    }
+
+    fn pdf(&self, hr: &HitRecord, wi: DVec3, wo: DVec3) -> f64 {
+        self.material.pdf(hr, wi, wo)
+    }
+
+    fn dispersive(&self) -> bool {
+        self.material.dispersive()
+    }
+
+    fn alpha(&self, hr: &HitRecord) -> f64 {
+        let alpha = self.alpha.scalar(hr) * self.material.alpha(hr);
+        match self.threshold {
+            Some(threshold) if alpha >= threshold => 1.0,
+            Some(_) => 0.0,
+            None => alpha,
+        }
+    }
+
+    fn interior(&self) -> Option<&dyn Medium> {
+        self.material.interior()
+    }
+
+    fn is_interface(&self) -> bool {
+        self.material.is_interface()
+    }
+
+    fn emitted(&self, hr: &HitRecord, wo: DVec3) -> DVec3 {
+        self.material.emitted(hr, wo)
+    }
+
+    fn exitance(&self, hr: &HitRecord) -> f64 {
+        self.material.exitance(hr)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::material::Lambertian;
+
+    use super::*;
+
+    #[test]
+    fn test_threshold() {
+        let r = Ray::new(DVec3::ZERO, DVec3::Z);
+        let lambertian = Lambertian::new();
+        let hr = HitRecord::new(&r, DVec3::Z, 1.0, DVec3::NEG_Z, &lambertian);
+        let material = Cutout::new(Arc::new(Lambertian::new()), 0.4);
+        assert_eq!(material.alpha(&hr), 0.4);
+        let material = material.threshold(0.5);
+        assert_eq!(material.alpha(&hr), 0.0);
+        let material = material.threshold(0.3);
+        assert_eq!(material.alpha(&hr), 1.0);
+    }
+}
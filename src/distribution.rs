@@ -0,0 +1,153 @@
+use glam::DVec2;
+
+/// Piecewise-constant density over [0, 1) proportional to a function
+/// tabulated at evenly spaced intervals, for importance sampling.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Distribution1D {
+    func: Vec<f64>,
+    cdf: Vec<f64>,
+    integral: f64,
+}
+
+impl Distribution1D {
+    /// Distribution of non-negative `func`, or uniform if it is zero
+    /// everywhere.
+    pub fn new(func: Vec<f64>) -> Self {
+        assert!(!func.is_empty());
+        let n = func.len() as f64;
+        let mut cdf = Vec::with_capacity(func.len() + 1);
+        cdf.push(0.0);
+        for f in &func {
+            cdf.push(cdf.last().unwrap() + f.max(0.0) / n);
+        }
+        let integral = *cdf.last().unwrap();
+        if integral > 0.0 {
+            cdf.iter_mut().for_each(|c| *c /= integral);
+        } else {
+            cdf.iter_mut()
+                .enumerate()
+                .for_each(|(i, c)| *c = i as f64 / n);
+        }
+        Self {
+            func,
+            cdf,
+            integral,
+        }
+    }
+
+    /// Integral of the function over [0, 1).
+    pub fn integral(&self) -> f64 {
+        self.integral
+    }
+
+    /// Map `u` in [0, 1) to a point with density `pdf`, returning the point,
+    /// its density and the interval it is in.
+    pub fn sample(&self, u: f64) -> (f64, f64, usize) {
+        // Last interval whose cumulative density starts at or below u
+        let i = self
+            .cdf
+            .partition_point(|&c| c <= u)
+            .clamp(1, self.func.len())
+            - 1;
+        let width = self.cdf[i + 1] - self.cdf[i];
+        let offset = if width > 0.0 {
+            (u - self.cdf[i]) / width
+        } else {
+            0.0
+        };
+        let x = (i as f64 + offset) / self.func.len() as f64;
+        (x.min(1.0 - f64::EPSILON), self.pdf(x), i)
+    }
+
+    /// Density of the point `x` in [0, 1).
+    pub fn pdf(&self, x: f64) -> f64 {
+        let n = self.func.len();
+        let i = ((x * n as f64) as usize).min(n - 1);
+        if self.integral > 0.0 {
+            self.func[i].max(0.0) / self.integral
+        } else {
+            1.0
+        }
+    }
+}
+
+/// Piecewise-constant density over the unit square, proportional to a
+/// function tabulated on a grid.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Distribution2D {
+    /// Distributions along X for each row.
+    conditional: Vec<Distribution1D>,
+    /// Distribution of the rows.
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    /// Distribution of `func`, given as rows of `width` values.
+    pub fn new(func: &[f64], width: usize) -> Self {
+        let conditional: Vec<_> = func
+            .chunks_exact(width)
+            .map(|row| Distribution1D::new(row.to_vec()))
+            .collect();
+        let marginal = Distribution1D::new(conditional.iter().map(|d| d.integral()).collect());
+        Self {
+            conditional,
+            marginal,
+        }
+    }
+
+    /// Map `u` in the unit square to a point with density `pdf`, returning
+    /// the point and its density. The point's Y coordinate selects the row.
+    pub fn sample(&self, u: DVec2) -> (DVec2, f64) {
+        let (y, pdf_y, row) = self.marginal.sample(u.y);
+        let (x, pdf_x, _) = self.conditional[row].sample(u.x);
+        (DVec2::new(x, y), pdf_x * pdf_y)
+    }
+
+    pub fn pdf(&self, p: DVec2) -> f64 {
+        let n = self.conditional.len();
+        let row = ((p.y * n as f64) as usize).min(n - 1);
+        self.marginal.pdf(p.y) * self.conditional[row].pdf(p.x)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_distribution_1d() {
+        let distribution = Distribution1D::new(vec![1.0, 0.0, 3.0]);
+        assert!((distribution.integral() - 4.0 / 3.0).abs() < 1e-12);
+        // A quarter of the samples fall in the first interval
+        let (x, pdf, i) = distribution.sample(0.125);
+        assert!((x - 1.0 / 6.0).abs() < 1e-12);
+        assert_eq!((pdf, i), (0.75, 0));
+        let (x, pdf, i) = distribution.sample(0.625);
+        assert!((x - 5.0 / 6.0).abs() < 1e-12);
+        assert_eq!((pdf, i), (2.25, 2));
+        assert_eq!(distribution.pdf(0.5), 0.0);
+
+        let uniform = Distribution1D::new(vec![0.0; 4]);
+        assert!((uniform.sample(0.3).0 - 0.3).abs() < 1e-12);
+        assert_eq!(uniform.pdf(0.3), 1.0);
+    }
+
+    #[test]
+    fn test_distribution_2d() {
+        let distribution = Distribution2D::new(&[1.0, 2.0, 3.0, 0.0, 0.0, 2.0], 3);
+        // The density integrates to one
+        let n = 60;
+        let total: f64 = (0..n * n)
+            .map(|i| {
+                let p = DVec2::new((i % n) as f64 + 0.5, (i / n) as f64 + 0.5) / n as f64;
+                distribution.pdf(p)
+            })
+            .sum();
+        assert!((total / (n * n) as f64 - 1.0).abs() < 1e-9);
+        for _ in 0..100 {
+            let (p, pdf) = distribution.sample(DVec2::new(rand::random(), rand::random()));
+            assert!(pdf > 0.0);
+            assert!((distribution.pdf(p) - pdf).abs() < 1e-9);
+        }
+    }
+}
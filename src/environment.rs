@@ -0,0 +1,189 @@
+use std::{
+    f64::consts::{PI, TAU},
+    path::Path,
+};
+
+use glam::{DVec2, DVec3};
+use image::ImageResult;
+use rand::random;
+
+use crate::{
+    distribution::Distribution2D,
+    light::{Light, LightSample},
+    texture::{ImageTexture, Texture, Wrap},
+    vector,
+};
+
+/// Light from all around the scene given by an equirectangular (latitude
+/// and longitude) image, usually an HDR photograph of a real environment.
+/// The top of the image is straight up and its centre faces -Z. Directions
+/// are sampled in proportion to the brightness of the image, so that small
+/// bright sources such as the sun are found quickly. The image wraps around
+/// horizontally, but not over the poles.
+pub struct EnvironmentMap {
+    image: ImageTexture,
+    intensity: f64,
+    rotation: f64,
+    distribution: Distribution2D,
+}
+
+impl EnvironmentMap {
+    pub fn new(image: ImageTexture) -> Self {
+        let image = image.wrap_uv(Wrap::Repeat, Wrap::Clamp);
+        let (width, height) = (image.width(), image.height());
+        // Rows near the poles cover less solid angle
+        let func: Vec<_> = (0..height)
+            .flat_map(|y| {
+                let sin_theta = (PI * (y as f64 + 0.5) / height as f64).sin();
+                let image = &image;
+                (0..width).map(move |x| vector::luminance(image.pixel(x, y)) * sin_theta)
+            })
+            .collect();
+        Self {
+            distribution: Distribution2D::new(&func, width as usize),
+            image,
+            intensity: 1.0,
+            rotation: 0.0,
+        }
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> ImageResult<Self> {
+        Ok(Self::new(ImageTexture::load(path)?))
+    }
+
+    pub fn intensity(self, intensity: f64) -> Self {
+        Self { intensity, ..self }
+    }
+
+    /// Turn the environment about the vertical axis, by degrees
+    /// anticlockwise seen from above.
+    pub fn rotation(self, rotation: f64) -> Self {
+        Self { rotation, ..self }
+    }
+
+    /// Position in the image of a direction, with Y increasing downwards
+    /// from the top of the image.
+    fn position(&self, direction: DVec3) -> DVec2 {
+        let d = direction.normalize();
+        let phi = (-d.x).atan2(d.z) + self.rotation.to_radians();
+        let theta = d.y.clamp(-1.0, 1.0).acos();
+        DVec2::new((phi / TAU).rem_euclid(1.0), theta / PI)
+    }
+
+    fn direction(&self, p: DVec2) -> DVec3 {
+        let phi = p.x * TAU - self.rotation.to_radians();
+        let (sin_theta, cos_theta) = (p.y * PI).sin_cos();
+        DVec3::new(-sin_theta * phi.sin(), cos_theta, sin_theta * phi.cos())
+    }
+
+    /// Solid angle density of a direction with density `pdf` over the image.
+    fn solid_angle_pdf(pdf: f64, p: DVec2) -> f64 {
+        let sin_theta = (p.y * PI).sin();
+        if sin_theta <= 0.0 {
+            0.0
+        } else {
+            pdf / (2.0 * PI * PI * sin_theta)
+        }
+    }
+}
+
+impl Light for EnvironmentMap {
+    fn sample(&self, _p: DVec3) -> Option<LightSample> {
+        let (p, pdf) = self.distribution.sample(DVec2::new(random(), random()));
+        let pdf = Self::solid_angle_pdf(pdf, p);
+        let wi = self.direction(p);
+        (pdf > 0.0).then(|| LightSample {
+            wi,
+            radiance: self.background(wi),
+            pdf,
+            distance: f64::INFINITY,
+        })
+    }
+
+    fn pdf(&self, _p: DVec3, wi: DVec3) -> f64 {
+        let p = self.position(wi);
+        Self::solid_angle_pdf(self.distribution.pdf(p), p)
+    }
+
+    fn background(&self, direction: DVec3) -> DVec3 {
+        let p = self.position(direction);
+        let uv = DVec2::new(p.x, 1.0 - p.y);
+        self.intensity * self.image.value(uv, direction)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn environment(width: u32, height: u32, pixel: impl Fn(u32, u32) -> DVec3) -> EnvironmentMap {
+        let pixels = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| pixel(x, y))
+            .collect();
+        EnvironmentMap::new(ImageTexture::new(width, height, pixels))
+    }
+
+    #[test]
+    fn test_directions() {
+        let map = environment(8, 4, |_, _| DVec3::ONE).rotation(30.0);
+        for d in [DVec3::NEG_Z, DVec3::X, DVec3::new(0.3, 0.5, -0.2)] {
+            let d = d.normalize();
+            assert!(map.direction(map.position(d)).abs_diff_eq(d, 1e-12));
+        }
+        let map = map.rotation(0.0);
+        assert!(map
+            .position(DVec3::NEG_Z)
+            .abs_diff_eq(DVec2::new(0.5, 0.5), 1e-12));
+        assert!(map
+            .position(DVec3::X)
+            .abs_diff_eq(DVec2::new(0.75, 0.5), 1e-12));
+        assert!(map.position(DVec3::Y).y.abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_wrapping() {
+        // The poles don't blend with the opposite edge of the image
+        let map = environment(2, 2, |_, y| DVec3::splat(y as f64));
+        assert_eq!(map.background(DVec3::Y), DVec3::ZERO);
+        assert_eq!(map.background(DVec3::NEG_Y), DVec3::ONE);
+        // The seam behind the centre blends both sides
+        let map = environment(2, 2, |x, _| DVec3::splat(x as f64));
+        assert_eq!(map.background(DVec3::Z), DVec3::splat(0.5));
+    }
+
+    #[test]
+    fn test_sampling() {
+        // A bright spot right of centre, in front of the camera
+        let map = environment(16, 8, |x, y| {
+            if (x, y) == (9, 3) {
+                DVec3::splat(1000.0)
+            } else {
+                DVec3::splat(0.1)
+            }
+        });
+        let spot = map.direction(DVec2::new(9.5 / 16.0, 3.5 / 8.0));
+        let n = 1000;
+        let mut near_spot = 0;
+        for _ in 0..n {
+            let s = map.sample(DVec3::ZERO).unwrap();
+            assert!((map.pdf(DVec3::ZERO, s.wi) - s.pdf).abs() <= 1e-6 * s.pdf);
+            if s.wi.dot(spot) > 0.9 {
+                near_spot += 1;
+            }
+        }
+        assert!(near_spot > n * 9 / 10, "{near_spot}");
+
+        // The density integrates to one over the sphere
+        let n = 64;
+        let total: f64 = (0..n * n)
+            .map(|i| {
+                let p = DVec2::new((i % n) as f64 + 0.5, (i / n) as f64 + 0.5) / n as f64;
+                let jacobian = 2.0 * PI * PI * (p.y * PI).sin();
+                map.pdf(DVec3::ZERO, map.direction(p)) * jacobian
+            })
+            .sum::<f64>()
+            / (n * n) as f64;
+        assert!((total - 1.0).abs() < 1e-6, "{total}");
+    }
+}
@@ -1,31 +1,74 @@
-use glam::DVec3;
+use glam::{DVec2, DVec3};
 
 use crate::{
     aabb::AABB,
     material::Material,
     ray::{Interval, Ray},
+    vector::Onb,
 };
 
+#[derive(Clone, Copy)]
 pub struct HitRecord<'a> {
     pub front_face: bool,
     pub material: &'a dyn Material,
+    /// Shading normal, facing the incoming ray. Normal and bump maps perturb
+    /// it away from `geometric_normal`.
     pub normal: DVec3,
+    /// Normal of the underlying surface, facing the incoming ray.
+    pub geometric_normal: DVec3,
     pub p: DVec3,
     pub t: f64,
+    /// Surface coordinates for texture lookups.
+    pub uv: DVec2,
+    /// Partial derivatives of the position with respect to `uv`, or zero if
+    /// the surface doesn't provide them.
+    pub dpdu: DVec3,
+    pub dpdv: DVec3,
+    /// Hero wavelength of the ray in spectral mode, in nanometres.
+    pub wavelength: Option<f64>,
 }
 
 impl<'a> HitRecord<'a> {
     pub fn new(r: &Ray, p: DVec3, t: f64, out_normal: DVec3, material: &'a dyn Material) -> Self {
         let front_face = r.direction.dot(out_normal) < 0.0;
+        let normal = if front_face { 1.0 } else { -1.0 } * out_normal;
         Self {
             front_face,
             material,
-            normal: if front_face { 1.0 } else { -1.0 } * out_normal,
+            normal,
+            geometric_normal: normal,
             p,
             t,
+            uv: DVec2::ZERO,
+            dpdu: DVec3::ZERO,
+            dpdv: DVec3::ZERO,
+            wavelength: r.wavelengths.map(|w| w.hero()),
         }
     }
 
+    pub fn with_uv(self, uv: DVec2) -> Self {
+        Self { uv, ..self }
+    }
+
+    pub fn with_derivatives(self, dpdu: DVec3, dpdv: DVec3) -> Self {
+        Self { dpdu, dpdv, ..self }
+    }
+
+    /// Shading normal on the outside of the surface.
+    pub fn outward_normal(&self) -> DVec3 {
+        if self.front_face {
+            self.normal
+        } else {
+            -self.normal
+        }
+    }
+
+    /// Shading frame around `normal` with its first tangent along `dpdu`,
+    /// so that anisotropic materials line up with the surface coordinates.
+    pub fn shading_frame(&self) -> Onb {
+        Onb::from_tangent(self.normal, self.dpdu)
+    }
+
     pub fn ray(&self, direction: DVec3) -> Ray {
         Ray::new(self.p, direction)
     }
@@ -35,4 +78,21 @@ pub trait Hit: Sync {
     fn aabb(&self) -> AABB;
     fn clipped_aabb(&self, axis: DVec3, t1: f64, t2: f64) -> AABB;
     fn hit<'a>(&'a self, r: &Ray, ray_t: Interval) -> Option<HitRecord<'a>>;
+
+    /// Surface area, or zero for surfaces that can't be sampled.
+    fn area(&self) -> f64 {
+        0.0
+    }
+
+    /// Sample a direction from `p` towards the surface, to light `p` with
+    /// an emissive surface. `None` if the surface can't be sampled from `p`.
+    fn sample_direction(&self, _p: DVec3) -> Option<DVec3> {
+        None
+    }
+
+    /// Solid angle density with which `sample_direction` chooses `wi` from
+    /// `p`.
+    fn direction_pdf(&self, _p: DVec3, _wi: DVec3) -> f64 {
+        0.0
+    }
 }
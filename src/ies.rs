@@ -0,0 +1,210 @@
+use std::{fs, io, path::Path};
+
+use glam::DVec3;
+
+use crate::vector::Onb;
+
+/// Measured distribution of a luminaire's intensity over directions, from
+/// an IES LM-63 photometric file. Only type C photometry, used for almost
+/// all architectural fittings, is supported. Vertical angles run from 0
+/// at the nadir, straight down from the fitting, to 180 at the zenith, and
+/// horizontal angles run around the nadir.
+#[derive(Clone, PartialEq, Debug)]
+pub struct IesProfile {
+    /// Vertical angles in degrees, ascending.
+    vertical: Vec<f64>,
+    /// Horizontal angles in degrees, ascending from 0.
+    horizontal: Vec<f64>,
+    /// Intensity in candela for each horizontal angle in turn, at each
+    /// vertical angle.
+    candela: Vec<f64>,
+    /// Greatest intensity in any direction.
+    max_candela: f64,
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+impl IesProfile {
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Parse the text of an LM-63 file, of any version from 1986 to 2019.
+    pub fn parse(text: &str) -> io::Result<Self> {
+        // Keywords come before the TILT line, and numbers after it
+        let mut lines = text.lines();
+        let tilt = lines
+            .find_map(|line| line.trim().strip_prefix("TILT="))
+            .ok_or_else(|| invalid("missing TILT line"))?;
+        let mut numbers = lines
+            .flat_map(|line| line.split(|c: char| c.is_whitespace() || c == ','))
+            .filter(|token| !token.is_empty())
+            .map(|token| token.parse::<f64>().map_err(|_| invalid("invalid number")));
+        let mut next = || {
+            numbers
+                .next()
+                .unwrap_or_else(|| Err(invalid("file ends early")))
+        };
+        if tilt.trim() == "INCLUDE" {
+            // Lamp tilt factors for fittings that can be aimed, which don't
+            // change the distribution of the fitting as installed
+            next()?;
+            let n = next()? as usize;
+            for _ in 0..2 * n {
+                next()?;
+            }
+        }
+        let [_lamps, _lumens, multiplier, n_vertical, n_horizontal, photometric_type] =
+            [(); 6].map(|_| next());
+        let (n_vertical, n_horizontal) = (n_vertical? as usize, n_horizontal? as usize);
+        if photometric_type? != 1.0 {
+            return Err(invalid("only type C photometry is supported"));
+        }
+        // Units, dimensions, ballast factor, a reserved field and watts
+        let [_units, _width, _length, _height, ballast, _reserved, _watts] =
+            [(); 7].map(|_| next());
+        let scale = multiplier? * ballast?;
+        let mut read = |n| (0..n).map(|_| next()).collect::<io::Result<Vec<_>>>();
+        let vertical = read(n_vertical)?;
+        let horizontal = read(n_horizontal)?;
+        let candela: Vec<_> = read(n_vertical * n_horizontal)?
+            .into_iter()
+            .map(|c| c * scale)
+            .collect();
+        let ascending = |angles: &[f64]| angles.windows(2).all(|w| w[0] < w[1]);
+        if vertical.is_empty() || horizontal.is_empty() {
+            return Err(invalid("no angles"));
+        }
+        if !ascending(&vertical) || !ascending(&horizontal) || horizontal[0] != 0.0 {
+            return Err(invalid("angles out of order"));
+        }
+        Ok(Self {
+            max_candela: candela.iter().copied().fold(0.0, f64::max),
+            vertical,
+            horizontal,
+            candela,
+        })
+    }
+
+    /// Greatest intensity in any direction, in candela.
+    pub fn max_candela(&self) -> f64 {
+        self.max_candela
+    }
+
+    /// Intensity in candela at vertical angle `theta` and horizontal angle
+    /// `phi` in degrees, interpolated between the measured angles.
+    pub fn candela(&self, theta: f64, phi: f64) -> f64 {
+        // Distributions covering less than a full turn are symmetric
+        let last = *self.horizontal.last().unwrap();
+        let mut phi = phi.rem_euclid(360.0);
+        if last <= 180.0 && phi > 180.0 {
+            phi = 360.0 - phi;
+        }
+        if last <= 90.0 && phi > 90.0 {
+            phi = 180.0 - phi;
+        }
+        let Some((v, tv)) = interval(&self.vertical, theta) else {
+            return 0.0;
+        };
+        let (h, th) = interval(&self.horizontal, phi).unwrap_or((0, 0.0));
+        let n = self.vertical.len();
+        let at = |h: usize, v: usize| {
+            let h = h.min(self.horizontal.len() - 1);
+            self.candela[h * n + v.min(n - 1)]
+        };
+        let lerp = |a: f64, b: f64, t: f64| a + (b - a) * t;
+        lerp(
+            lerp(at(h, v), at(h, v + 1), tv),
+            lerp(at(h + 1, v), at(h + 1, v + 1), tv),
+            th,
+        )
+    }
+
+    /// Intensity towards `direction` relative to the brightest direction,
+    /// for a fitting whose nadir points along `nadir` and whose C0 plane,
+    /// where horizontal angles start, is towards `c0`. Horizontal angles
+    /// increase clockwise looking along the nadir.
+    pub fn relative(&self, nadir: DVec3, c0: DVec3, direction: DVec3) -> f64 {
+        let max = self.max_candela();
+        if max <= 0.0 {
+            return 0.0;
+        }
+        let onb = Onb::from_tangent(nadir.normalize(), c0);
+        let d = direction.normalize();
+        let theta = d.dot(onb.w).clamp(-1.0, 1.0).acos().to_degrees();
+        let phi = d.dot(onb.v).atan2(d.dot(onb.u)).to_degrees();
+        self.candela(theta, phi) / max
+    }
+}
+
+/// Index of the interval of ascending `angles` containing `x` and the
+/// fraction of the way across it, or `None` outside them.
+fn interval(angles: &[f64], x: f64) -> Option<(usize, f64)> {
+    if x < angles[0] || x > *angles.last().unwrap() {
+        return None;
+    }
+    let i = angles.partition_point(|&a| a <= x).clamp(1, angles.len()) - 1;
+    match angles.get(i + 1) {
+        Some(next) => Some((i, (x - angles[i]) / (next - angles[i]))),
+        None => Some((i, 0.0)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const DOWNLIGHT: &str = "IESNA:LM-63-2002
+[TEST] downlight
+[MANUFAC] none
+TILT=NONE
+1 1000 2 3 1 1 2 0.1 0.1 0
+1.0 1.0 20
+0 45 90
+0
+100 50 0
+";
+
+    #[test]
+    fn test_parse() {
+        let profile = IesProfile::parse(DOWNLIGHT).unwrap();
+        assert_eq!(profile.max_candela(), 200.0);
+        assert_eq!(profile.candela(0.0, 0.0), 200.0);
+        assert_eq!(profile.candela(22.5, 123.0), 150.0);
+        assert_eq!(profile.candela(90.0, 0.0), 0.0);
+        assert_eq!(profile.candela(135.0, 0.0), 0.0);
+        let direction = DVec3::new(22.5f64.to_radians().tan(), 0.0, -1.0);
+        assert!((profile.relative(DVec3::NEG_Z, DVec3::X, direction) - 0.75).abs() < 1e-12);
+
+        let tilted = DOWNLIGHT.replace("TILT=NONE", "TILT=INCLUDE\n1\n2\n0 90\n1 0.5");
+        assert_eq!(IesProfile::parse(&tilted).unwrap(), profile);
+        assert!(IesProfile::parse(&DOWNLIGHT.replace("100 50 0", "100 50")).is_err());
+        assert!(IesProfile::parse(&DOWNLIGHT.replace("1 1 2 0.1", "1 2 2 0.1")).is_err());
+    }
+
+    #[test]
+    fn test_symmetry() {
+        // Brighter across the fitting than along it, given for one quadrant
+        let profile = IesProfile::parse(
+            "TILT=NONE
+1 -1 1 2 2 1 1 0 0 0
+1 1 0
+0,90
+0,90
+10,0
+20,0
+",
+        )
+        .unwrap();
+        for (phi, expected) in [(0.0, 10.0), (45.0, 15.0), (90.0, 20.0), (135.0, 15.0)] {
+            assert_eq!(profile.candela(0.0, phi), expected);
+            assert_eq!(profile.candela(0.0, 360.0 - phi), expected);
+        }
+        // The C0 plane of the fitting turns the distribution about the nadir
+        let direction = DVec3::new(0.0, -1.0, 1.0);
+        assert!((profile.relative(DVec3::NEG_Y, DVec3::Z, direction) - 0.25).abs() < 1e-12);
+        assert!((profile.relative(DVec3::NEG_Y, DVec3::X, direction) - 0.5).abs() < 1e-12);
+    }
+}
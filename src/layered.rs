@@ -0,0 +1,360 @@
+use std::sync::Arc;
+
+use glam::DVec3;
+use rand::random;
+
+use crate::{
+    hit::HitRecord,
+    material::{BsdfSample, Lobe, Material},
+    medium::Medium,
+    microfacet::{self, TrowbridgeReitz},
+    ray::Ray,
+    texture::TextureRef,
+    vector::{self, Onb},
+};
+
+/// Blend of two materials, weighted towards `b` by `factor`.
+pub struct Mix {
+    a: Arc<dyn Material>,
+    b: Arc<dyn Material>,
+    factor: TextureRef,
+}
+
+impl Mix {
+    pub fn new(a: Arc<dyn Material>, b: Arc<dyn Material>, factor: impl Into<TextureRef>) -> Self {
+        Self {
+            a,
+            b,
+            factor: factor.into(),
+        }
+    }
+
+    fn factor(&self, hr: &HitRecord) -> f64 {
+        self.factor.scalar(hr).clamp(0.0, 1.0)
+    }
+}
+
+impl Material for Mix {
+    fn sample(&self, r: &Ray, hr: &HitRecord) -> Option<BsdfSample> {
+        let t = self.factor(hr);
+        let (material, probability) = if random::<f64>() < t {
+            (&self.b, t)
+        } else {
+            (&self.a, 1.0 - t)
+        };
+        let s = material.sample(r, hr)?;
+        if s.lobe.is_specular() {
+            return Some(BsdfSample {
+                value: s.value * probability,
+                pdf: s.pdf * probability,
+                ..s
+            });
+        }
+        let wo = -r.direction;
+        let pdf = self.pdf(hr, s.wi, wo);
+        (pdf > 0.0).then(|| BsdfSample {
+            value: self.eval(hr, s.wi, wo),
+            pdf,
+            ..s
+        })
+    }
+
+    fn eval(&self, hr: &HitRecord, wi: DVec3, wo: DVec3) -> DVec3 {
+        let t = self.factor(hr);
+        (1.0 - t) * self.a.eval(hr, wi, wo) + t * self.b.eval(hr, wi, wo)
+    }
+
+    fn pdf(&self, hr: &HitRecord, wi: DVec3, wo: DVec3) -> f64 {
+        let t = self.factor(hr);
+        (1.0 - t) * self.a.pdf(hr, wi, wo) + t * self.b.pdf(hr, wi, wo)
+    }
+
+    fn dispersive(&self) -> bool {
+        self.a.dispersive() || self.b.dispersive()
+    }
+
+    fn alpha(&self, hr: &HitRecord) -> f64 {
+        let t = self.factor(hr);
+        (1.0 - t) * self.a.alpha(hr) + t * self.b.alpha(hr)
+    }
+
+    /// Medium of the side that is an interface, or else of the side that has
+    /// one, with `a` winning ties. Both sides bound the same object, so a
+    /// Mix should only give them different media by mistake.
+    fn interior(&self) -> Option<&dyn Medium> {
+        let (first, second) = if self.b.is_interface() && !self.a.is_interface() {
+            (&self.b, &self.a)
+        } else {
+            (&self.a, &self.b)
+        };
+        first.interior().or_else(|| second.interior())
+    }
+
+    /// Only when both sides are, as otherwise some rays scatter.
+    fn is_interface(&self) -> bool {
+        self.a.is_interface() && self.b.is_interface()
+    }
+
+    fn emitted(&self, hr: &HitRecord, wo: DVec3) -> DVec3 {
+        let t = self.factor(hr);
+        (1.0 - t) * self.a.emitted(hr, wo) + t * self.b.emitted(hr, wo)
+    }
+
+    /// Blended like the emission, by how much each material emits along the
+    /// normal, so that a material that doesn't emit counts for nothing.
+    fn exitance(&self, hr: &HitRecord) -> f64 {
+        let t = self.factor(hr);
+        let along_normal = |m: &Arc<dyn Material>| vector::luminance(m.emitted(hr, hr.normal));
+        let (a, b) = ((1.0 - t) * along_normal(&self.a), t * along_normal(&self.b));
+        if a + b > 0.0 {
+            (a * self.a.exitance(hr) + b * self.b.exitance(hr)) / (a + b)
+        } else {
+            (1.0 - t) * self.a.exitance(hr) + t * self.b.exitance(hr)
+        }
+    }
+}
+
+/// A thin dielectric layer, such as lacquer or varnish, over a base material.
+/// Light reflected by the base is attenuated by the Fresnel transmittance of
+/// the coat on the way in and out, and by the coat's `tint`, which is its
+/// transmittance on a round trip at normal incidence. Interreflections inside
+/// the coat are neglected.
+pub struct Coated {
+    base: Arc<dyn Material>,
+    ior: f64,
+    roughness: f64,
+    tint: DVec3,
+}
+
+impl Coated {
+    pub fn new(base: Arc<dyn Material>) -> Self {
+        Self {
+            base,
+            ior: 1.5,
+            roughness: 0.0,
+            tint: DVec3::ONE,
+        }
+    }
+
+    pub fn ior(self, ior: f64) -> Self {
+        Self { ior, ..self }
+    }
+
+    pub fn roughness(self, roughness: f64) -> Self {
+        Self { roughness, ..self }
+    }
+
+    pub fn tint(self, tint: DVec3) -> Self {
+        Self { tint, ..self }
+    }
+
+    fn distribution(&self) -> TrowbridgeReitz {
+        TrowbridgeReitz::from_roughness(self.roughness, 0.0)
+    }
+
+    fn fresnel(&self, cos_theta: f64) -> f64 {
+        microfacet::fresnel_dielectric(cos_theta.abs(), self.ior)
+    }
+
+    /// Probability of sampling reflection from the coat rather than the base.
+    fn coat_probability(&self, hr: &HitRecord, wo: DVec3) -> f64 {
+        self.fresnel(wo.dot(hr.normal)).max(0.05)
+    }
+
+    /// Attenuation of light passing through the coat to the base and back.
+    fn transmittance(&self, hr: &HitRecord, wi: DVec3, wo: DVec3) -> DVec3 {
+        let (cos_i, cos_o) = (wi.dot(hr.normal).abs(), wo.dot(hr.normal).abs());
+        // Path length through the coat relative to normal incidence
+        let refracted = |cos: f64| (1.0 - (1.0 - cos * cos) / (self.ior * self.ior)).sqrt();
+        let length = 0.5 * (1.0 / refracted(cos_i) + 1.0 / refracted(cos_o));
+        (1.0 - self.fresnel(cos_i)) * (1.0 - self.fresnel(cos_o)) * self.tint.powf(length)
+    }
+
+    /// Reflection from a rough coat, times the cosine, and its density.
+    fn coat(&self, hr: &HitRecord, wi: DVec3, wo: DVec3) -> (DVec3, f64) {
+        let distribution = self.distribution();
+        let onb = Onb::new(hr.normal);
+        let (wi, wo) = (onb.to_local(wi), onb.to_local(wo));
+        if distribution.is_smooth() || wi.z <= 0.0 || wo.z <= 0.0 {
+            return (DVec3::ZERO, 0.0);
+        }
+        let wm = (wi + wo).normalize();
+        let f = self.fresnel(wo.dot(wm));
+        let value = f * distribution.d(wm) * distribution.g(wo, wi) / (4.0 * wo.z);
+        let pdf = distribution.d_visible(wo, wm) / (4.0 * wo.dot(wm).abs());
+        (DVec3::splat(value), pdf)
+    }
+}
+
+impl Material for Coated {
+    fn sample(&self, r: &Ray, hr: &HitRecord) -> Option<BsdfSample> {
+        let wo = -r.direction;
+        let p_coat = self.coat_probability(hr, wo);
+        if random::<f64>() < p_coat {
+            let distribution = self.distribution();
+            if distribution.is_smooth() {
+                return Some(BsdfSample {
+                    value: DVec3::splat(self.fresnel(wo.dot(hr.normal))),
+                    wi: vector::reflect(r.direction, hr.normal),
+                    pdf: p_coat,
+                    lobe: Lobe::SPECULAR | Lobe::REFLECTION,
+                });
+            }
+            let onb = Onb::new(hr.normal);
+            let wo_local = onb.to_local(wo);
+            let wi = onb.to_world(vector::reflect(-wo_local, distribution.sample_wm(wo_local)));
+            let pdf = self.pdf(hr, wi, wo);
+            return (pdf > 0.0).then(|| BsdfSample {
+                value: self.eval(hr, wi, wo),
+                wi,
+                pdf,
+                lobe: Lobe::GLOSSY | Lobe::REFLECTION,
+            });
+        }
+
+        let s = self.base.sample(r, hr)?;
+        if s.lobe.is_specular() {
+            return Some(BsdfSample {
+                value: s.value * self.transmittance(hr, s.wi, wo),
+                pdf: s.pdf * (1.0 - p_coat),
+                ..s
+            });
+        }
+        let pdf = self.pdf(hr, s.wi, wo);
+        (pdf > 0.0).then(|| BsdfSample {
+            value: self.eval(hr, s.wi, wo),
+            pdf,
+            ..s
+        })
+    }
+
+    fn eval(&self, hr: &HitRecord, wi: DVec3, wo: DVec3) -> DVec3 {
+        let (coat, _) = self.coat(hr, wi, wo);
+        coat + self.transmittance(hr, wi, wo) * self.base.eval(hr, wi, wo)
+    }
+
+    fn pdf(&self, hr: &HitRecord, wi: DVec3, wo: DVec3) -> f64 {
+        let p_coat = self.coat_probability(hr, wo);
+        let (_, coat_pdf) = self.coat(hr, wi, wo);
+        p_coat * coat_pdf + (1.0 - p_coat) * self.base.pdf(hr, wi, wo)
+    }
+
+    fn dispersive(&self) -> bool {
+        self.base.dispersive()
+    }
+
+    fn alpha(&self, hr: &HitRecord) -> f64 {
+        self.base.alpha(hr)
+    }
+
+    fn interior(&self) -> Option<&dyn Medium> {
+        self.base.interior()
+    }
+
+    /// Emission of the base, attenuated on its way out through the coat.
+    fn emitted(&self, hr: &HitRecord, wo: DVec3) -> DVec3 {
+        let emitted = self.base.emitted(hr, wo);
+        if emitted == DVec3::ZERO {
+            return emitted;
+        }
+        let cos_o = wo.normalize().dot(hr.normal).abs();
+        let refracted = (1.0 - (1.0 - cos_o * cos_o) / (self.ior * self.ior)).sqrt();
+        // The tint is for a round trip, so half of it one way
+        emitted * (1.0 - self.fresnel(cos_o)) * self.tint.powf(0.5 / refracted)
+    }
+
+    fn exitance(&self, hr: &HitRecord) -> f64 {
+        self.base.exitance(hr)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::f64::consts::PI;
+
+    use crate::material::{DiffuseLight, Lambertian, Metal};
+
+    use super::*;
+
+    fn albedo(material: &dyn Material, r: &Ray, hr: &HitRecord) -> DVec3 {
+        let n = 20_000;
+        let total: DVec3 = (0..n)
+            .filter_map(|_| material.sample(r, hr))
+            .map(|s| s.weight())
+            .sum();
+        total / n as f64
+    }
+
+    #[test]
+    fn test_mix() {
+        let white = Arc::new(Lambertian::new().albedo(DVec3::ONE));
+        let mirror = Arc::new(Metal::new().albedo(DVec3::new(1.0, 0.0, 0.0)));
+        let material = Mix::new(white, mirror, 0.25);
+        let r = Ray::new(DVec3::new(0.0, 1.0, 0.0), DVec3::new(0.3, -1.0, 0.0));
+        let hr = HitRecord::new(&r, DVec3::ZERO, 1.0, DVec3::Y, &material);
+        let albedo = albedo(&material, &r, &hr);
+        // Silver reflects about 97% at this angle
+        assert!((albedo.x - 0.99).abs() < 0.03, "{albedo}");
+        assert!((albedo.y - 0.75).abs() < 0.03, "{albedo}");
+    }
+
+    #[test]
+    fn test_coated() {
+        let white = Arc::new(Lambertian::new().albedo(DVec3::ONE));
+        let r = Ray::new(DVec3::new(0.0, 1.0, 0.0), DVec3::new(0.3, -1.0, 0.0));
+        for material in [
+            Coated::new(white.clone()),
+            Coated::new(white.clone()).roughness(0.3),
+            Coated::new(white.clone()).tint(DVec3::new(0.5, 0.8, 1.0)),
+        ] {
+            let hr = HitRecord::new(&r, DVec3::ZERO, 1.0, DVec3::Y, &material);
+            let albedo = albedo(&material, &r, &hr);
+            assert!(albedo.max_element() <= 1.02, "{albedo}");
+            // Most light still makes it through a clear coat
+            assert!(albedo.z > 0.8, "{albedo}");
+            for _ in 0..100 {
+                let Some(s) = material.sample(&r, &hr) else {
+                    continue;
+                };
+                if !s.lobe.is_specular() {
+                    let pdf = material.pdf(&hr, s.wi, -r.direction);
+                    assert!((s.pdf - pdf).abs() <= 1e-9 * pdf);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_mix_emission() {
+        let light = Arc::new(DiffuseLight::new().intensity(2.0));
+        let narrow = Arc::new(DiffuseLight::new().spread(60.0));
+        let white = Arc::new(Lambertian::new().albedo(DVec3::ONE));
+        let r = Ray::new(DVec3::Y, DVec3::NEG_Y);
+        // Only the emissive side emits, and sets the exitance
+        let material = Mix::new(light.clone(), white, 0.25);
+        let hr = HitRecord::new(&r, DVec3::ZERO, 1.0, DVec3::Y, &material);
+        assert_eq!(material.emitted(&hr, DVec3::Y), DVec3::splat(1.5));
+        assert!((material.exitance(&hr) - PI).abs() < 1e-12);
+        // Exitances are blended by emission
+        let material = Mix::new(light, narrow, 0.5);
+        let hr = HitRecord::new(&r, DVec3::ZERO, 1.0, DVec3::Y, &material);
+        assert_eq!(material.emitted(&hr, DVec3::Y), DVec3::splat(1.5));
+        let expected = (2.0 * PI + 0.75 * PI) / 3.0;
+        assert!((material.exitance(&hr) - expected).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_coated_emission() {
+        let light = Arc::new(DiffuseLight::new().spread(60.0));
+        let r = Ray::new(DVec3::Y, DVec3::NEG_Y);
+        let material = Coated::new(light.clone());
+        let hr = HitRecord::new(&r, DVec3::ZERO, 1.0, DVec3::Y, &material);
+        // A clear coat reflects 4% back at normal incidence
+        let emitted = material.emitted(&hr, DVec3::Y);
+        assert!(emitted.abs_diff_eq(DVec3::splat(0.96), 1e-12), "{emitted}");
+        assert_eq!(material.exitance(&hr), light.exitance());
+        let tinted = Coated::new(light).tint(DVec3::new(0.25, 1.0, 1.0));
+        let emitted = tinted.emitted(&hr, DVec3::Y);
+        assert!((emitted.x - 0.96 * 0.5).abs() < 1e-12, "{emitted}");
+    }
+}
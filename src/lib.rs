@@ -0,0 +1,47 @@
+//! A CPU path tracer based on Ray Tracing in One Weekend.
+//!
+//! Build a scene from `Hit` primitives with `Material`s, wrap them in a `BVH`
+//! and a `Scene`, and render it with a `Camera` created from a `Config`.
+
+pub mod aabb;
+pub mod bvh;
+pub mod camera;
+pub mod cutout;
+pub mod distribution;
+pub mod environment;
+pub mod hit;
+pub mod ies;
+pub mod layered;
+pub mod light;
+pub mod light_tree;
+pub mod material;
+pub mod medium;
+pub mod microfacet;
+pub mod normal_map;
+pub mod principled;
+pub mod progress;
+pub mod ray;
+pub mod scene;
+pub mod sky;
+pub mod spectrum;
+pub mod sphere;
+pub mod stats;
+pub mod subsurface;
+pub mod texture;
+pub mod thin_film;
+mod util;
+pub mod vector;
+pub mod volume;
+
+pub use crate::{
+    aabb::AABB,
+    bvh::BVH,
+    camera::{Camera, CancellationToken, Config},
+    hit::{Hit, HitRecord},
+    light::Light,
+    material::{BsdfSample, Lobe, Material},
+    ray::{Interval, Ray},
+    scene::Scene,
+    sphere::Sphere,
+    texture::{Texture, TextureRef},
+};
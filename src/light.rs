@@ -0,0 +1,465 @@
+use std::{f64::consts::PI, sync::Arc};
+
+use glam::DVec3;
+
+use crate::{
+    aabb::AABB,
+    hit::{Hit, HitRecord},
+    ies::IesProfile,
+    light_tree::LightBounds,
+    material::DiffuseLight,
+    ray::{Interval, Ray},
+    util::default_struct,
+    vector,
+};
+
+/// Light arriving at a point from a sampled direction.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct LightSample {
+    /// Direction towards the light.
+    pub wi: DVec3,
+    /// Radiance from `wi`, or irradiance for delta lights.
+    pub radiance: DVec3,
+    /// Solid angle density of `wi`, or 1 for delta lights.
+    pub pdf: f64,
+    /// Distance to the light, infinite for lights at infinity.
+    pub distance: f64,
+}
+
+/// Source of light that paths can be connected to directly, rather than only
+/// reaching it by chance.
+pub trait Light: Send + Sync {
+    /// Sample a direction from `p` towards the light.
+    fn sample(&self, p: DVec3) -> Option<LightSample>;
+
+    /// Solid angle density with which `sample` chooses `wi` from `p`.
+    fn pdf(&self, _p: DVec3, _wi: DVec3) -> f64 {
+        0.0
+    }
+
+    /// Radiance reaching rays that leave the scene along `direction`, for
+    /// lights at infinity.
+    fn background(&self, _direction: DVec3) -> DVec3 {
+        DVec3::ZERO
+    }
+
+    /// Whether light arrives from a single direction, so that only light
+    /// sampling can find it and rays never hit it.
+    fn is_delta(&self) -> bool {
+        false
+    }
+
+    /// Bounds for choosing among many lights by their contributions, or
+    /// `None` for lights at infinity, which are chosen uniformly.
+    fn bounds(&self) -> Option<LightBounds> {
+        None
+    }
+
+    /// Primitive that emits the light, for telling which light a ray that
+    /// hits an emissive surface has reached.
+    fn emitter(&self) -> Option<&dyn Hit> {
+        None
+    }
+}
+
+/// Multiple importance sampling weight of a strategy with density `a`
+/// against one with density `b`.
+pub fn power_heuristic(a: f64, b: f64) -> f64 {
+    let (a2, b2) = (a * a, b * b);
+    if a2 + b2 > 0.0 {
+        a2 / (a2 + b2)
+    } else {
+        0.0
+    }
+}
+
+default_struct!(
+    /// Sky shading linearly from `horizon` to `zenith`, as in Ray Tracing in
+    /// One Weekend.
+    #[derive(Copy)]
+    Gradient {
+        horizon: DVec3 = DVec3::ONE,
+        zenith: DVec3 = DVec3::new(0.5, 0.7, 1.0),
+    }
+);
+
+impl Light for Gradient {
+    fn sample(&self, p: DVec3) -> Option<LightSample> {
+        let wi = vector::random_unit();
+        Some(LightSample {
+            wi,
+            radiance: self.background(wi),
+            pdf: self.pdf(p, wi),
+            distance: f64::INFINITY,
+        })
+    }
+
+    fn pdf(&self, _p: DVec3, _wi: DVec3) -> f64 {
+        1.0 / (4.0 * PI)
+    }
+
+    fn background(&self, direction: DVec3) -> DVec3 {
+        let a = (direction.normalize().y + 1.0) / 2.0;
+        (1.0 - a) * self.horizon + a * self.zenith
+    }
+}
+
+/// Light reaching `p` from a point at `position` with `radiance`,
+/// attenuated with distance `d` by `1 / d^falloff`.
+fn point_sample(position: DVec3, p: DVec3, radiance: DVec3, falloff: f64) -> Option<LightSample> {
+    let offset = position - p;
+    let distance = offset.length();
+    (distance > 0.0).then(|| LightSample {
+        wi: offset / distance,
+        radiance: radiance / distance.powf(falloff),
+        pdf: 1.0,
+        distance,
+    })
+}
+
+/// Fraction of the intensity of a light with an optional `profile` aimed
+/// along `nadir`, with its C0 plane towards `c0`, that shines along
+/// `direction`.
+fn profile(profile: &Option<Arc<IesProfile>>, nadir: DVec3, c0: DVec3, direction: DVec3) -> f64 {
+    profile
+        .as_ref()
+        .map_or(1.0, |profile| profile.relative(nadir, c0, direction))
+}
+
+default_struct!(
+    /// Light shining equally in all directions from `position`. A `falloff`
+    /// of 2 is the physical inverse square law; lower values let light
+    /// carry further. A photometric `profile` varies the intensity with
+    /// direction, scaled so that `intensity` is its brightest, with the
+    /// nadir of the fitting along `direction` and its C0 plane, where the
+    /// horizontal angles of the profile start, towards `c0`.
+    Point {
+        position: DVec3 = DVec3::ZERO,
+        colour: DVec3 = DVec3::ONE,
+        intensity: f64 = 1.0,
+        falloff: f64 = 2.0,
+        direction: DVec3 = DVec3::NEG_Y,
+        c0: DVec3 = DVec3::X,
+        #[into]
+        profile: Option<Arc<IesProfile>> = None,
+    }
+);
+
+impl Light for Point {
+    fn sample(&self, p: DVec3) -> Option<LightSample> {
+        let profile = profile(&self.profile, self.direction, self.c0, p - self.position);
+        let radiance = self.colour * self.intensity * profile;
+        point_sample(self.position, p, radiance, self.falloff)
+    }
+
+    fn is_delta(&self) -> bool {
+        true
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        Some(LightBounds {
+            aabb: AABB::bounding_box([self.position]),
+            power: 4.0 * PI * self.intensity * vector::luminance(self.colour),
+            axis: DVec3::Y,
+            cos_theta_o: -1.0,
+            cos_theta_e: 0.0,
+            two_sided: false,
+        })
+    }
+}
+
+default_struct!(
+    /// Light shining from `position` in a cone around `direction`, with
+    /// `angle` the half-angle of the cone in degrees. Its intensity fades
+    /// smoothly to nothing across the outer `edge` degrees of the cone. As
+    /// for `Point`, a photometric `profile` aimed along `direction` and
+    /// turned towards `c0` varies the intensity within the cone, which can
+    /// be widened to 180 degrees to leave the profile alone.
+    Spot {
+        position: DVec3 = DVec3::ZERO,
+        direction: DVec3 = DVec3::NEG_Y,
+        c0: DVec3 = DVec3::X,
+        colour: DVec3 = DVec3::ONE,
+        intensity: f64 = 1.0,
+        falloff: f64 = 2.0,
+        angle: f64 = 30.0,
+        edge: f64 = 5.0,
+        #[into]
+        profile: Option<Arc<IesProfile>> = None,
+    }
+);
+
+impl Spot {
+    /// Fraction of the full intensity shining along `direction`.
+    pub fn cone(&self, direction: DVec3) -> f64 {
+        let cos_theta = direction.normalize().dot(self.direction.normalize());
+        let theta = cos_theta.clamp(-1.0, 1.0).acos().to_degrees();
+        if self.edge <= 0.0 {
+            return if theta <= self.angle { 1.0 } else { 0.0 };
+        }
+        let x = ((self.angle - theta) / self.edge).clamp(0.0, 1.0);
+        x * x * (3.0 - 2.0 * x)
+    }
+}
+
+impl Light for Spot {
+    fn sample(&self, p: DVec3) -> Option<LightSample> {
+        let cone = self.cone(p - self.position);
+        if cone <= 0.0 {
+            return None;
+        }
+        let profile = profile(&self.profile, self.direction, self.c0, p - self.position);
+        let radiance = self.colour * self.intensity * cone * profile;
+        point_sample(self.position, p, radiance, self.falloff)
+    }
+
+    fn is_delta(&self) -> bool {
+        true
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        // Full intensity out to theta_o, fading to nothing by theta_e
+        let theta_o = (self.angle - self.edge).max(0.0);
+        Some(LightBounds {
+            aabb: AABB::bounding_box([self.position]),
+            power: 4.0 * PI * self.intensity * vector::luminance(self.colour),
+            axis: self.direction.normalize(),
+            cos_theta_o: theta_o.to_radians().cos(),
+            cos_theta_e: (self.angle - theta_o).to_radians().cos(),
+            two_sided: false,
+        })
+    }
+}
+
+default_struct!(
+    /// Parallel light travelling along `direction` from infinitely far away,
+    /// giving an irradiance of `intensity` to surfaces facing it.
+    #[derive(Copy)]
+    Directional {
+        direction: DVec3 = DVec3::NEG_Y,
+        colour: DVec3 = DVec3::ONE,
+        intensity: f64 = 1.0,
+    }
+);
+
+impl Light for Directional {
+    fn sample(&self, _p: DVec3) -> Option<LightSample> {
+        Some(LightSample {
+            wi: -self.direction.normalize(),
+            radiance: self.colour * self.intensity,
+            pdf: 1.0,
+            distance: f64::INFINITY,
+        })
+    }
+
+    fn is_delta(&self) -> bool {
+        true
+    }
+}
+
+/// Light from an emissive `surface`, sampled from `p` with
+/// `Hit::sample_direction`.
+pub fn sample_surface(surface: &(impl Hit + ?Sized), p: DVec3) -> Option<LightSample> {
+    let wi = surface.sample_direction(p)?;
+    let hr = surface.hit(&Ray::new(p, wi), Interval::new(0.0, f64::INFINITY))?;
+    Some(LightSample {
+        wi,
+        radiance: hr.material.emitted(&hr, -wi),
+        pdf: surface.direction_pdf(p, wi),
+        distance: hr.t,
+    })
+}
+
+/// Any primitive that can be sampled (see `Hit::sample_direction`) made to
+/// glow with `material` in place of its own. Clones of it should be added to
+/// both the primitives and the lights of a scene, sharing the material that
+/// hits on the primitive are matched to the light by. Textured emission makes
+/// screens and stained glass, and `power` keeps the total light the same
+/// when the primitive is resized. Panics if the primitive can't be sampled,
+/// as shown by a zero `Hit::area`.
+#[derive(Clone)]
+pub struct AreaLight<H> {
+    shape: H,
+    material: Arc<DiffuseLight>,
+}
+
+impl<H: Hit> AreaLight<H> {
+    pub fn new(shape: H, material: DiffuseLight) -> Self {
+        assert!(
+            shape.area() > 0.0,
+            "area lights need a primitive that can be sampled"
+        );
+        Self {
+            shape,
+            material: Arc::new(material),
+        }
+    }
+
+    /// Scale the emission so that the total power, as luminance, is `power`
+    /// where the emission is white.
+    pub fn power(mut self, power: f64) -> Self {
+        let area = self.shape.area();
+        let material = Arc::make_mut(&mut self.material);
+        material.intensity = power / (area * material.exitance());
+        self
+    }
+}
+
+impl<H: Hit> Hit for AreaLight<H> {
+    fn aabb(&self) -> AABB {
+        self.shape.aabb()
+    }
+
+    fn clipped_aabb(&self, axis: DVec3, t1: f64, t2: f64) -> AABB {
+        self.shape.clipped_aabb(axis, t1, t2)
+    }
+
+    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord<'_>> {
+        let hr = self.shape.hit(r, ray_t)?;
+        Some(HitRecord {
+            material: &*self.material,
+            ..hr
+        })
+    }
+
+    fn area(&self) -> f64 {
+        self.shape.area()
+    }
+
+    fn sample_direction(&self, p: DVec3) -> Option<DVec3> {
+        self.shape.sample_direction(p)
+    }
+
+    fn direction_pdf(&self, p: DVec3, wi: DVec3) -> f64 {
+        self.shape.direction_pdf(p, wi)
+    }
+}
+
+impl<H: Hit + Send> Light for AreaLight<H> {
+    fn sample(&self, p: DVec3) -> Option<LightSample> {
+        sample_surface(self, p)
+    }
+
+    fn pdf(&self, p: DVec3, wi: DVec3) -> f64 {
+        self.direction_pdf(p, wi)
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        // Emission where a ray from above meets the surface, for a texture
+        let aabb = self.aabb();
+        let above = aabb.centroid() + (aabb.size().length() + 1.0) * DVec3::Y;
+        let emission = self
+            .sample_direction(above)
+            .and_then(|wi| self.hit(&Ray::new(above, wi), Interval::new(0.0, f64::INFINITY)))
+            .map_or(DVec3::ONE, |hr| self.material.emission.value(&hr));
+        let radiance = self.material.intensity * vector::luminance(emission);
+        Some(LightBounds {
+            aabb,
+            power: self.area() * self.material.exitance() * radiance,
+            axis: DVec3::Y,
+            cos_theta_o: -1.0,
+            cos_theta_e: self.material.spread.clamp(0.0, 90.0).to_radians().cos(),
+            two_sided: self.material.two_sided,
+        })
+    }
+
+    fn emitter(&self) -> Option<&dyn Hit> {
+        Some(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        volume::{Grid, GridMedium, GridVolume},
+        Sphere,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_point() {
+        let light = Point::new()
+            .position(DVec3::new(0.0, 2.0, 0.0))
+            .intensity(8.0);
+        let s = light.sample(DVec3::ZERO).unwrap();
+        assert_eq!(
+            (s.wi, s.radiance, s.distance),
+            (DVec3::Y, DVec3::splat(2.0), 2.0)
+        );
+        let s = light.falloff(1.0).sample(DVec3::ZERO).unwrap();
+        assert_eq!(s.radiance, DVec3::splat(4.0));
+    }
+
+    #[test]
+    fn test_profile() {
+        // Brightest straight down and dark from the side up
+        let profile = IesProfile::parse("TILT=NONE\n1 -1 1 2 1 1 1 0 0 0\n1 1 0\n0 90\n0\n50 0\n");
+        let light = Point::new()
+            .position(DVec3::Y)
+            .profile(Arc::new(profile.unwrap()));
+        assert_eq!(light.sample(DVec3::ZERO).unwrap().radiance, DVec3::ONE);
+        let s = light.sample(DVec3::new(1.0, 0.0, 0.0)).unwrap();
+        assert!(
+            s.radiance.abs_diff_eq(DVec3::splat(0.25), 1e-12),
+            "{}",
+            s.radiance
+        );
+        assert_eq!(
+            light.sample(DVec3::new(1.0, 1.0, 0.0)).unwrap().radiance,
+            DVec3::ZERO
+        );
+    }
+
+    #[test]
+    fn test_spot() {
+        let light = Spot::new().position(DVec3::Y).angle(30.0).edge(10.0);
+        assert_eq!(light.cone(DVec3::NEG_Y), 1.0);
+        assert_eq!(light.cone(DVec3::X), 0.0);
+        let edge = light.cone(DVec3::new(25f64.to_radians().tan(), -1.0, 0.0));
+        assert!((edge - 0.5).abs() < 1e-9, "{edge}");
+        assert!(light.sample(DVec3::ZERO).is_some());
+        assert!(light.sample(DVec3::new(0.0, 2.0, 0.0)).is_none());
+    }
+
+    #[test]
+    fn test_area_light() {
+        // The same power from lamps of different sizes and spreads
+        let lamp = |radius, material| {
+            let sphere = Sphere::new(DVec3::ZERO, radius, Arc::new(DiffuseLight::new()));
+            AreaLight::new(sphere, material).power(10.0)
+        };
+        for material in [
+            DiffuseLight::new(),
+            DiffuseLight::new().spread(30.0).two_sided(true),
+        ] {
+            for radius in [0.5, 2.0] {
+                let power = lamp(radius, material.clone()).bounds().unwrap().power;
+                assert!((power - 10.0).abs() < 1e-9, "{power}");
+            }
+        }
+
+        // Light within the spread, from the front face unless two-sided
+        let narrow = lamp(1.0, DiffuseLight::new().spread(30.0));
+        let radiance = |light: &AreaLight<Sphere>, origin: DVec3, direction: DVec3| {
+            let r = Ray::new(origin, direction);
+            let hr = light.hit(&r, Interval::new(0.0, f64::INFINITY)).unwrap();
+            hr.material.emitted(&hr, -direction)
+        };
+        let head_on = radiance(&narrow, DVec3::new(0.0, 0.0, 5.0), DVec3::NEG_Z);
+        assert!(head_on.abs_diff_eq(DVec3::splat(10.0 / (PI * PI)), 1e-9));
+        let grazing = radiance(&narrow, DVec3::new(0.9, 0.0, 5.0), DVec3::NEG_Z);
+        assert_eq!(grazing, DVec3::ZERO);
+        assert_eq!(radiance(&narrow, DVec3::ZERO, DVec3::X), DVec3::ZERO);
+        let inside = lamp(1.0, DiffuseLight::new().two_sided(true));
+        assert_ne!(radiance(&inside, DVec3::ZERO, DVec3::X), DVec3::ZERO);
+    }
+
+    #[test]
+    #[should_panic(expected = "can be sampled")]
+    fn test_area_light_unsampled() {
+        let bounds = AABB::bounding_box([DVec3::ZERO, DVec3::ONE]);
+        let volume = GridVolume::new(GridMedium::new(Grid::new([1, 1, 1], vec![1.0]), bounds));
+        AreaLight::new(volume, DiffuseLight::new());
+    }
+}
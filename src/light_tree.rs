@@ -0,0 +1,395 @@
+use std::f64::consts::PI;
+
+use glam::{DQuat, DVec3};
+use rand::random;
+
+use crate::{aabb::AABB, light::Light};
+
+/// Where a light is and where it shines, for estimating how much it
+/// contributes to points it might light.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct LightBounds {
+    pub aabb: AABB,
+    /// Total power emitted, as luminance.
+    pub power: f64,
+    /// Axis of the cone of surface normals (or emission directions).
+    pub axis: DVec3,
+    /// Cosine of the half-angle of the cone of normals around `axis`.
+    pub cos_theta_o: f64,
+    /// Cosine of the angle beyond the normals that light spreads to.
+    pub cos_theta_e: f64,
+    /// Whether the surfaces emit from both sides.
+    pub two_sided: bool,
+}
+
+/// Cosine of `a - b`, or 1 if `b` is larger, given sines and cosines.
+fn cos_sub_clamped((sin_a, cos_a): (f64, f64), (sin_b, cos_b): (f64, f64)) -> f64 {
+    if cos_a > cos_b {
+        1.0
+    } else {
+        cos_a * cos_b + sin_a * sin_b
+    }
+}
+
+/// Sine of `a - b`, or 0 if `b` is larger.
+fn sin_sub_clamped((sin_a, cos_a): (f64, f64), (sin_b, cos_b): (f64, f64)) -> f64 {
+    if cos_a > cos_b {
+        0.0
+    } else {
+        sin_a * cos_b - cos_a * sin_b
+    }
+}
+
+fn sin_cos(cos: f64) -> (f64, f64) {
+    ((1.0 - cos * cos).max(0.0).sqrt(), cos)
+}
+
+impl LightBounds {
+    /// Conservative estimate of the light reaching `p` on a surface with
+    /// normal `n`, which is zero in media, after Conty Estevez and Kulla
+    /// (2018).
+    pub fn importance(&self, p: DVec3, n: DVec3) -> f64 {
+        let centroid = self.aabb.centroid();
+        let d2 = p
+            .distance_squared(centroid)
+            .max(self.aabb.size().length() / 2.0);
+        let wi = (p - centroid).normalize_or_zero();
+        let mut cos_theta_w = self.axis.dot(wi);
+        if self.two_sided {
+            cos_theta_w = cos_theta_w.abs();
+        }
+        // Angle subtended by the bounds, from their bounding sphere
+        let radius2 = self.aabb.size().length_squared() / 4.0;
+        let cos_theta_b = if p.distance_squared(centroid) < radius2 {
+            -1.0
+        } else {
+            (1.0 - radius2 / p.distance_squared(centroid))
+                .max(0.0)
+                .sqrt()
+        };
+        let theta_b = sin_cos(cos_theta_b);
+        // Smallest angle between the normals and the direction to p
+        let theta_w = sin_cos(cos_theta_w);
+        let theta_o = sin_cos(self.cos_theta_o);
+        let theta_x = (
+            sin_sub_clamped(theta_w, theta_o),
+            cos_sub_clamped(theta_w, theta_o),
+        );
+        let cos_theta_p = cos_sub_clamped(theta_x, theta_b);
+        if cos_theta_p <= self.cos_theta_e {
+            return 0.0;
+        }
+        let mut importance = self.power * cos_theta_p / d2;
+        if n != DVec3::ZERO {
+            let theta_i = sin_cos(wi.dot(n).abs());
+            importance *= cos_sub_clamped(theta_i, theta_b);
+        }
+        importance.max(0.0)
+    }
+
+    /// Estimate of how poorly the bounds predict the light from within
+    /// them, from their power, size and spread of directions (Pharr et al.
+    /// 2023, section 12.6.3).
+    fn cost(&self) -> f64 {
+        let theta_o = self.cos_theta_o.clamp(-1.0, 1.0).acos();
+        let theta_e = self.cos_theta_e.clamp(-1.0, 1.0).acos();
+        let theta_w = (theta_o + theta_e).min(PI);
+        let (sin_theta_o, cos_theta_o) = theta_o.sin_cos();
+        let m_omega = 2.0 * PI * (1.0 - cos_theta_o)
+            + PI / 2.0
+                * (2.0 * theta_w * sin_theta_o
+                    - (theta_o - 2.0 * theta_w).cos()
+                    - 2.0 * theta_o * sin_theta_o
+                    + cos_theta_o);
+        self.power * m_omega * self.aabb.size().length()
+    }
+
+    /// Bounds of two sets of lights together.
+    pub fn union(&self, other: &Self) -> Self {
+        let (axis, cos_theta_o) = cone_union(
+            (self.axis, self.cos_theta_o),
+            (other.axis, other.cos_theta_o),
+        );
+        Self {
+            aabb: AABB::union([self.aabb, other.aabb]),
+            power: self.power + other.power,
+            axis,
+            cos_theta_o,
+            cos_theta_e: self.cos_theta_e.min(other.cos_theta_e),
+            two_sided: self.two_sided || other.two_sided,
+        }
+    }
+}
+
+/// Smallest cone containing two cones, given by their axes and the cosines
+/// of their half-angles.
+fn cone_union((w_a, cos_a): (DVec3, f64), (w_b, cos_b): (DVec3, f64)) -> (DVec3, f64) {
+    let (theta_a, theta_b) = (cos_a.clamp(-1.0, 1.0).acos(), cos_b.clamp(-1.0, 1.0).acos());
+    let theta_d = w_a.dot(w_b).clamp(-1.0, 1.0).acos();
+    if (theta_d + theta_b).min(PI) <= theta_a {
+        return (w_a, cos_a);
+    }
+    if (theta_d + theta_a).min(PI) <= theta_b {
+        return (w_b, cos_b);
+    }
+    let theta_o = (theta_a + theta_d + theta_b) / 2.0;
+    let axis = w_a.cross(w_b);
+    if theta_o >= PI || axis.length_squared() == 0.0 {
+        return (w_a, -1.0);
+    }
+    let w = DQuat::from_axis_angle(axis.normalize(), theta_o - theta_a) * w_a;
+    (w, theta_o.cos())
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Node {
+    bounds: LightBounds,
+    /// Index of the light at a leaf, or of the second child of an interior
+    /// node, whose first child follows it.
+    index: usize,
+    leaf: bool,
+}
+
+/// Chooses which light to sample at a point, in proportion to estimates of
+/// their contributions from a bounding volume hierarchy over the lights.
+/// Lights at infinity have no bounds and are chosen uniformly, together
+/// with the tree as a whole.
+#[derive(Clone, Debug)]
+pub struct LightTree {
+    nodes: Vec<Node>,
+    infinite: Vec<usize>,
+    /// Leaf node of each light, if it's in the tree.
+    leaves: Vec<Option<usize>>,
+}
+
+impl LightTree {
+    pub fn new(lights: &[Box<dyn Light>]) -> Self {
+        let mut infinite = Vec::new();
+        let mut bounded = Vec::new();
+        for (i, light) in lights.iter().enumerate() {
+            match light.bounds() {
+                Some(bounds) if bounds.power > 0.0 => bounded.push((i, bounds)),
+                Some(_) => {}
+                None => infinite.push(i),
+            }
+        }
+        let mut tree = Self {
+            nodes: Vec::with_capacity(2 * bounded.len()),
+            infinite,
+            leaves: vec![None; lights.len()],
+        };
+        if !bounded.is_empty() {
+            tree.build(&mut bounded);
+        }
+        tree
+    }
+
+    fn build(&mut self, lights: &mut [(usize, LightBounds)]) -> LightBounds {
+        if let [(index, bounds)] = *lights {
+            self.leaves[index] = Some(self.nodes.len());
+            self.nodes.push(Node {
+                bounds,
+                index,
+                leaf: true,
+            });
+            return bounds;
+        }
+        // Sort along the longest axis of the centres and split where the
+        // lights on each side are least spread out in space and direction
+        let centroids = AABB::bounding_box(lights.iter().map(|(_, b)| b.aabb.centroid()));
+        let size = centroids.size();
+        let axis = (0..3).find(|&i| size[i] == size.max_element()).unwrap();
+        lights
+            .sort_by(|(_, a), (_, b)| a.aabb.centroid()[axis].total_cmp(&b.aabb.centroid()[axis]));
+        let mut before = vec![lights[0].1];
+        for (_, bounds) in &lights[1..] {
+            before.push(before.last().unwrap().union(bounds));
+        }
+        let mut after = vec![lights[lights.len() - 1].1];
+        for (_, bounds) in lights[..lights.len() - 1].iter().rev() {
+            after.push(after.last().unwrap().union(bounds));
+        }
+        let mid = (1..lights.len())
+            .min_by(|&a, &b| {
+                let cost = |i: usize| before[i - 1].cost() + after[lights.len() - 1 - i].cost();
+                cost(a).total_cmp(&cost(b))
+            })
+            .unwrap();
+        let node = self.nodes.len();
+        self.nodes.push(Node {
+            bounds: lights[0].1,
+            index: 0,
+            leaf: false,
+        });
+        let (left, right) = lights.split_at_mut(mid);
+        let left = self.build(left);
+        self.nodes[node].index = self.nodes.len();
+        let bounds = left.union(&self.build(right));
+        self.nodes[node].bounds = bounds;
+        bounds
+    }
+
+    /// Probability of choosing the tree rather than a light at infinity.
+    fn tree_pmf(&self) -> f64 {
+        if self.nodes.is_empty() {
+            0.0
+        } else {
+            1.0 / (self.infinite.len() + 1) as f64
+        }
+    }
+
+    /// Probability of choosing any one light at infinity.
+    pub fn infinite_pmf(&self) -> f64 {
+        if self.infinite.is_empty() {
+            0.0
+        } else {
+            (1.0 - self.tree_pmf()) / self.infinite.len() as f64
+        }
+    }
+
+    /// Choose a light to sample at `p` on a surface with normal `n` (zero in
+    /// media), returning its index and the probability of choosing it.
+    pub fn sample(&self, p: DVec3, n: DVec3) -> Option<(usize, f64)> {
+        if self.nodes.is_empty() && self.infinite.is_empty() {
+            return None;
+        }
+        let tree_pmf = self.tree_pmf();
+        let u = random::<f64>();
+        if u >= tree_pmf {
+            let i = (((u - tree_pmf) / (1.0 - tree_pmf)) * self.infinite.len() as f64) as usize;
+            return Some((
+                self.infinite[i.min(self.infinite.len() - 1)],
+                self.infinite_pmf(),
+            ));
+        }
+        let mut node = 0;
+        let mut pmf = tree_pmf;
+        loop {
+            let Node {
+                bounds,
+                index,
+                leaf,
+            } = self.nodes[node];
+            if leaf {
+                return (bounds.importance(p, n) > 0.0).then_some((index, pmf));
+            }
+            let children = [node + 1, index];
+            let [a, b] = children.map(|c| self.nodes[c].bounds.importance(p, n));
+            if a + b <= 0.0 {
+                return None;
+            }
+            let first = random::<f64>() * (a + b) < a;
+            pmf *= if first { a } else { b } / (a + b);
+            node = children[if first { 0 } else { 1 }];
+        }
+    }
+
+    /// Lights in the tree whose bounds contain `point`, which are the only
+    /// ones that could have emitted from it.
+    pub fn containing(&self, point: DVec3) -> Vec<usize> {
+        let mut lights = Vec::new();
+        let mut stack = if self.nodes.is_empty() {
+            vec![]
+        } else {
+            vec![0]
+        };
+        while let Some(node) = stack.pop() {
+            let Node {
+                bounds,
+                index,
+                leaf,
+            } = self.nodes[node];
+            if !contains(&bounds.aabb, point) {
+                continue;
+            }
+            if leaf {
+                lights.push(index);
+            } else {
+                stack.extend([node + 1, index]);
+            }
+        }
+        lights
+    }
+
+    /// Probability of choosing the light with index `light` at `p` on a
+    /// surface with normal `n`, as `sample` does.
+    pub fn pmf(&self, p: DVec3, n: DVec3, light: usize) -> f64 {
+        if self.infinite.contains(&light) {
+            return self.infinite_pmf();
+        }
+        let Some(&Some(leaf)) = self.leaves.get(light) else {
+            return 0.0;
+        };
+        // The first child of a node is followed by all of its descendants,
+        // and the second child by the rest
+        let mut node = 0;
+        let mut pmf = self.tree_pmf();
+        while node != leaf {
+            let index = self.nodes[node].index;
+            let children = [node + 1, index];
+            let [a, b] = children.map(|c| self.nodes[c].bounds.importance(p, n));
+            if a + b <= 0.0 {
+                return 0.0;
+            }
+            let second = leaf >= index;
+            pmf *= if second { b } else { a } / (a + b);
+            node = children[second as usize];
+        }
+        if self.nodes[leaf].bounds.importance(p, n) > 0.0 {
+            pmf
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Whether `p` is in `aabb`, allowing for rounding of points on its faces.
+fn contains(aabb: &AABB, p: DVec3) -> bool {
+    let eps = 1e-6 * (1.0 + aabb.size().max_element());
+    p.cmpge(aabb.min - eps).all() && p.cmple(aabb.max + eps).all()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::light::{Gradient, Point};
+
+    use super::*;
+
+    #[test]
+    fn test_cone_union() {
+        let (w, cos) = cone_union((DVec3::X, 1.0), (DVec3::Y, 1.0));
+        assert!(w.abs_diff_eq(DVec3::new(1.0, 1.0, 0.0).normalize(), 1e-12));
+        assert!((cos - (PI / 4.0).cos()).abs() < 1e-12);
+        // One cone inside the other
+        assert_eq!(
+            cone_union((DVec3::X, 0.0), (DVec3::Y, 1.0)),
+            (DVec3::X, 0.0)
+        );
+    }
+
+    #[test]
+    fn test_light_tree() {
+        // A bright and a dim light near the origin and one far away
+        let lights: Vec<Box<dyn Light>> = vec![
+            Box::new(Point::new().position(DVec3::X).intensity(10.0)),
+            Box::new(Gradient::new()),
+            Box::new(Point::new().position(DVec3::NEG_X)),
+            Box::new(Point::new().position(DVec3::splat(100.0))),
+        ];
+        let tree = LightTree::new(&lights);
+        assert_eq!(tree.infinite_pmf(), 0.5);
+        let mut counts = [0; 4];
+        let n = 10_000;
+        for _ in 0..n {
+            let (i, pmf) = tree.sample(DVec3::ZERO, DVec3::ZERO).unwrap();
+            counts[i] += 1;
+            let expected = tree.pmf(DVec3::ZERO, DVec3::ZERO, i);
+            assert!((expected - pmf).abs() < 1e-12, "{expected} {pmf}");
+        }
+        let total: f64 = (0..4).map(|i| tree.pmf(DVec3::ZERO, DVec3::ZERO, i)).sum();
+        assert!((total - 1.0).abs() < 1e-12, "{total}");
+        assert!(counts[0] > 4 * counts[2], "{counts:?}");
+        assert!(counts[3] < counts[2] / 10, "{counts:?}");
+        assert!((counts[1] as f64 / n as f64 - 0.5).abs() < 0.03);
+    }
+}
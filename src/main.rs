@@ -1,28 +1,15 @@
 use std::sync::Arc;
 
 use bevy::app::App;
-use bvh::BVH;
-use camera::Config;
 use glam::DVec3;
-use material::*;
 use rand::{random, thread_rng, Rng};
+use raytracer::{material::*, vector, Config, Hit, Scene, Sphere, BVH};
 
-use crate::{hit::Hit, sphere::Sphere};
-
-mod aabb;
 mod app;
-mod bvh;
-mod camera;
-mod hit;
-mod material;
-mod ray;
-mod sphere;
-mod util;
-mod vector;
 
 macro_rules! make {
-    (Metal albedo( $r:expr, $g:expr, $b:expr ) fuzz($f:expr)) => {
-        Arc::new(Metal::new().fuzz($f).albedo(DVec3::new($r, $g, $b))) as Arc<dyn Material>
+    (Metal albedo( $r:expr, $g:expr, $b:expr ) roughness($f:expr)) => {
+        Arc::new(Metal::new().roughness($f).albedo(DVec3::new($r, $g, $b))) as Arc<dyn Material>
     };
     (Lambertian albedo( $r:expr, $g:expr, $b:expr )) => {
         Arc::new(Lambertian::new().albedo(DVec3::new($r, $g, $b))) as Arc<dyn Material>
@@ -51,7 +38,7 @@ fn main() {
     let material2 = make!(Lambertian albedo(0.4, 0.2, 0.1));
     objects.push(make!(sphere 1.0, (-4.0, 1.0, 0.0), material2));
 
-    let material3 = make!(Metal albedo(0.7, 0.6, 0.5) fuzz(0.0));
+    let material3 = make!(Metal albedo(0.7, 0.6, 0.5) roughness(0.0));
     objects.push(make!(sphere 1.0, (4.0, 1.0, 0.0), material3));
 
     for a in -11..11 {
@@ -70,8 +57,8 @@ fn main() {
                 // Metal
                 1 => {
                     let albedo = vector::random_range(0.5..=1.0);
-                    let fuzz: f64 = random();
-                    Arc::new(Metal::new().albedo(albedo).fuzz(fuzz)) as Arc<dyn Material>
+                    let roughness: f64 = random();
+                    Arc::new(Metal::new().albedo(albedo).roughness(roughness)) as Arc<dyn Material>
                 }
                 // Glass
                 2 => Arc::new(Dielectric::new().ir(1.5)) as Arc<dyn Material>,
@@ -81,7 +68,7 @@ fn main() {
         }
     }
 
-    let world = BVH::new(objects);
+    let world = Scene::new(BVH::new(objects));
 
     let config = Config::new()
         .aspect_ratio(16.0 / 9.0)
@@ -95,5 +82,16 @@ fn main() {
         .samples_per_pixel(750)
         .max_depth(50);
 
-    config.camera().render(&world);
+    let camera = config.camera();
+    let cancellation_token = camera.cancellation_token();
+    ctrlc::set_handler(move || {
+        if cancellation_token.is_cancelled() {
+            // A second Ctrl-C abandons the render entirely
+            std::process::exit(130);
+        }
+        eprintln!("Cancelling, finishing the current pass...");
+        cancellation_token.cancel();
+    })
+    .expect("Ctrl-C handler can be installed");
+    camera.render(&world);
 }
@@ -1,65 +1,681 @@
+use std::{f64::consts::PI, ops::BitOr};
+
 use glam::DVec3;
 use rand::random;
 
-use crate::{hit::HitRecord, ray::Ray, util::default_struct, vector};
+use crate::{
+    hit::HitRecord,
+    medium::Medium,
+    microfacet::{self, TrowbridgeReitz},
+    ray::Ray,
+    texture::TextureRef,
+    thin_film::ThinFilm,
+    util::default_struct,
+    vector::{self, Onb},
+};
+
+/// Classification of a scattering lobe. Flags are combined with `|`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Lobe(u8);
+
+impl Lobe {
+    pub const DIFFUSE: Self = Self(1 << 0);
+    pub const GLOSSY: Self = Self(1 << 1);
+    pub const SPECULAR: Self = Self(1 << 2);
+    pub const REFLECTION: Self = Self(1 << 3);
+    pub const TRANSMISSION: Self = Self(1 << 4);
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn is_specular(self) -> bool {
+        self.contains(Self::SPECULAR)
+    }
+
+    pub fn is_transmission(self) -> bool {
+        self.contains(Self::TRANSMISSION)
+    }
+}
+
+impl BitOr for Lobe {
+    type Output = Self;
 
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+/// A scattered direction sampled from a material.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct BsdfSample {
+    /// The BSDF times the cosine between `wi` and the normal. For specular
+    /// lobes this is the attenuation of the chosen lobe.
+    pub value: DVec3,
+    /// Direction of the scattered ray.
+    pub wi: DVec3,
+    /// Solid angle density of `wi`. For specular lobes this is the discrete
+    /// probability of choosing the lobe.
+    pub pdf: f64,
+    pub lobe: Lobe,
+}
+
+impl BsdfSample {
+    /// Throughput weight of the scattered ray.
+    pub fn weight(&self) -> DVec3 {
+        self.value / self.pdf
+    }
+}
+
+/// Surface scattering. Directions point away from the surface: `wo` towards
+/// the viewer (the reverse of the incoming ray) and `wi` along the scattered
+/// ray.
 pub trait Material: Send + Sync {
-    fn scatter(&self, r: &Ray, hr: &HitRecord) -> Option<(DVec3, Ray)>;
+    /// Sample a direction to continue a path arriving along `r`.
+    fn sample(&self, r: &Ray, hr: &HitRecord) -> Option<BsdfSample>;
+
+    /// The BSDF times the cosine between `wi` and the normal. Specular lobes
+    /// can't be evaluated and contribute nothing.
+    fn eval(&self, _hr: &HitRecord, _wi: DVec3, _wo: DVec3) -> DVec3 {
+        DVec3::ZERO
+    }
+
+    /// Solid angle density with which `sample` chooses `wi`, excluding
+    /// specular lobes.
+    fn pdf(&self, _hr: &HitRecord, _wi: DVec3, _wo: DVec3) -> f64 {
+        0.0
+    }
+
+    /// Whether scattering depends on the wavelength of the ray. In spectral
+    /// mode, paths scattered by a dispersive material keep only their hero
+    /// wavelength (`HitRecord::wavelength`).
+    fn dispersive(&self) -> bool {
+        false
+    }
+
+    /// Opacity at a hit. Below one, `BVH::hit` skips the hit with
+    /// probability `1 - alpha` and carries on along the ray.
+    fn alpha(&self, _hr: &HitRecord) -> f64 {
+        1.0
+    }
+
+    /// Medium filling the inside of closed objects with this material. Rays
+    /// transmitted into the front face travel through it until they are
+    /// transmitted out again.
+    fn interior(&self) -> Option<&dyn Medium> {
+        None
+    }
+
+    /// Whether the surface only bounds a medium, letting all rays through
+    /// unchanged.
+    fn is_interface(&self) -> bool {
+        false
+    }
+
+    /// Radiance emitted from the hit towards `wo`, back along the incoming
+    /// ray.
+    fn emitted(&self, _hr: &HitRecord, _wo: DVec3) -> DVec3 {
+        DVec3::ZERO
+    }
+
+    /// Power emitted per unit area and radiance at the hit, π for surfaces
+    /// that emit equally in every direction from their front face.
+    fn exitance(&self, _hr: &HitRecord) -> f64 {
+        PI
+    }
 }
 
 default_struct!(Lambertian {
-    albedo: DVec3 = DVec3::ZERO,
+    #[into]
+    albedo: TextureRef = DVec3::ZERO.into(),
 });
 
-default_struct!(Metal {
-    albedo: DVec3 = DVec3::ZERO,
-    fuzz: f64 = 0.0,
-});
+default_struct!(
+    /// Microfacet conductor. `eta` and `k` are the complex index of
+    /// refraction for red, green and blue light, `albedo` tints the
+    /// reflection, and `anisotropy` stretches the highlight along `dpdu`,
+    /// the U direction of the surface. A `thin_film` adds iridescence, as
+    /// on heat-tinted steel.
+    Metal {
+        #[into]
+        albedo: TextureRef = DVec3::ONE.into(),
+        #[into]
+        roughness: TextureRef = 0.0.into(),
+        anisotropy: f64 = 0.0,
+        eta: DVec3 = DVec3::new(0.155, 0.117, 0.138),
+        k: DVec3 = DVec3::new(4.828, 3.122, 2.147),
+        thin_film: Option<ThinFilm> = None,
+    }
+);
 
-default_struct!(Dielectric { ir: f64 = 1.5 });
+default_struct!(
+    /// Smooth or, when `roughness` is above zero, frosted glass. Light
+    /// travelling inside is attenuated by `absorption` per unit distance.
+    /// The index of refraction is `ir` unless a `dispersion` model is set.
+    /// A `thin_film` on the outside gives soap bubble iridescence.
+    #[derive(Copy)]
+    Dielectric {
+        ir: f64 = 1.5,
+        dispersion: Dispersion = Dispersion::None,
+        roughness: f64 = 0.0,
+        absorption: DVec3 = DVec3::ZERO,
+        thin_film: Option<ThinFilm> = None,
+    }
+);
 
-impl Material for Lambertian {
-    fn scatter(&self, _r: &Ray, hr: &HitRecord) -> Option<(DVec3, Ray)> {
-        let mut scatter_direction = hr.normal + vector::random_unit();
-        if vector::near_zero(scatter_direction) {
-            scatter_direction = hr.normal;
+default_struct!(
+    /// Surface that emits `emission` scaled by `intensity` from its front
+    /// face, or both faces if `two_sided`, and absorbs all light, as in Ray
+    /// Tracing: The Next Week. Light leaves within `spread` degrees of the
+    /// normal, so that narrowing it from 90 (diffuse) makes a softbox.
+    DiffuseLight {
+        #[into]
+        emission: TextureRef = DVec3::ONE.into(),
+        intensity: f64 = 1.0,
+        two_sided: bool = false,
+        spread: f64 = 90.0,
+    }
+);
+
+/// Wavelength-dependent index of refraction, with wavelengths in micrometres.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Dispersion {
+    None,
+    /// n = a + b / λ²
+    Cauchy {
+        a: f64,
+        b: f64,
+    },
+    /// n² = 1 + Σ bᵢλ² / (λ² - cᵢ)
+    Sellmeier {
+        b: [f64; 3],
+        c: [f64; 3],
+    },
+}
+
+impl Dispersion {
+    /// Schott N-BK7 crown glass.
+    pub const BK7: Self = Self::Sellmeier {
+        b: [1.03961212, 0.231792344, 1.01046945],
+        c: [0.00600069867, 0.0200179144, 103.560653],
+    };
+    pub const DIAMOND: Self = Self::Sellmeier {
+        b: [0.3306, 4.3356, 0.0],
+        c: [0.030625, 0.011236, 0.0],
+    };
+
+    /// Index of refraction at a wavelength in nanometres.
+    pub fn ior(&self, wavelength: f64) -> Option<f64> {
+        let lambda2 = (wavelength / 1000.0).powi(2);
+        match *self {
+            Self::None => None,
+            Self::Cauchy { a, b } => Some(a + b / lambda2),
+            Self::Sellmeier { b, c } => {
+                let n2 = 1.0
+                    + b.iter()
+                        .zip(c)
+                        .map(|(b, c)| b * lambda2 / (lambda2 - c))
+                        .sum::<f64>();
+                Some(n2.sqrt())
+            }
         }
-        // let scatter_direction = V3::random_on_hemisphere(&hr.normal);
-        Some((self.albedo, hr.ray(scatter_direction)))
+    }
+}
+
+impl Material for Lambertian {
+    fn sample(&self, r: &Ray, hr: &HitRecord) -> Option<BsdfSample> {
+        let wi = Onb::new(hr.normal).to_world(vector::random_cosine_direction());
+        let pdf = self.pdf(hr, wi, -r.direction);
+        (pdf > 0.0).then(|| BsdfSample {
+            value: self.eval(hr, wi, -r.direction),
+            wi,
+            pdf,
+            lobe: Lobe::DIFFUSE | Lobe::REFLECTION,
+        })
+    }
+
+    fn eval(&self, hr: &HitRecord, wi: DVec3, _wo: DVec3) -> DVec3 {
+        self.albedo.value(hr) / PI * wi.dot(hr.normal).max(0.0)
+    }
+
+    fn pdf(&self, hr: &HitRecord, wi: DVec3, _wo: DVec3) -> f64 {
+        vector::cosine_hemisphere_pdf(wi.dot(hr.normal))
+    }
+}
+
+impl Metal {
+    pub fn gold() -> Self {
+        Self::new()
+            .eta(DVec3::new(0.143, 0.374, 1.442))
+            .k(DVec3::new(3.983, 2.385, 1.603))
+    }
+
+    pub fn copper() -> Self {
+        Self::new()
+            .eta(DVec3::new(0.200, 0.924, 1.102))
+            .k(DVec3::new(3.912, 2.452, 2.142))
+    }
+
+    pub fn aluminium() -> Self {
+        Self::new()
+            .eta(DVec3::new(1.657, 0.880, 0.521))
+            .k(DVec3::new(9.224, 6.270, 4.837))
+    }
+
+    pub fn silver() -> Self {
+        Self::new()
+    }
+
+    fn distribution(&self, hr: &HitRecord) -> TrowbridgeReitz {
+        TrowbridgeReitz::from_roughness(self.roughness.scalar(hr), self.anisotropy)
+    }
+
+    fn fresnel(&self, hr: &HitRecord, cos_theta: f64) -> DVec3 {
+        let reflectance = match self.thin_film {
+            Some(film) => film.reflectance(cos_theta, 1.0, self.eta, self.k, hr.wavelength),
+            None => microfacet::fresnel_conductor(cos_theta, self.eta, self.k),
+        };
+        self.albedo.value(hr) * reflectance
     }
 }
 
 impl Material for Metal {
-    fn scatter(&self, r: &Ray, hr: &HitRecord) -> Option<(DVec3, Ray)> {
-        let reflected =
-            vector::reflect(r.direction.normalize(), hr.normal) + self.fuzz * vector::random_unit();
-        if reflected.dot(hr.normal) <= 0.0 {
+    fn sample(&self, r: &Ray, hr: &HitRecord) -> Option<BsdfSample> {
+        let onb = hr.shading_frame();
+        let wo = onb.to_local(-r.direction);
+        let distribution = self.distribution(hr);
+        if distribution.is_smooth() {
+            return Some(BsdfSample {
+                value: self.fresnel(hr, wo.z.abs()),
+                wi: vector::reflect(r.direction, hr.normal),
+                pdf: 1.0,
+                lobe: Lobe::SPECULAR | Lobe::REFLECTION,
+            });
+        }
+
+        let wm = distribution.sample_wm(wo);
+        let wi = vector::reflect(-wo, wm);
+        if wi.z <= 0.0 {
             return None;
         }
-        Some((self.albedo, hr.ray(reflected)))
+        let (wi, wo) = (onb.to_world(wi), onb.to_world(wo));
+        Some(BsdfSample {
+            value: self.eval(hr, wi, wo),
+            wi,
+            pdf: self.pdf(hr, wi, wo),
+            lobe: Lobe::GLOSSY | Lobe::REFLECTION,
+        })
+    }
+
+    fn eval(&self, hr: &HitRecord, wi: DVec3, wo: DVec3) -> DVec3 {
+        let distribution = self.distribution(hr);
+        let onb = hr.shading_frame();
+        let (wi, wo) = (onb.to_local(wi), onb.to_local(wo));
+        if distribution.is_smooth() || wi.z <= 0.0 || wo.z <= 0.0 {
+            return DVec3::ZERO;
+        }
+        let wm = (wi + wo).normalize();
+        let f = self.fresnel(hr, wo.dot(wm).abs());
+        f * distribution.d(wm) * distribution.g(wo, wi) / (4.0 * wo.z)
+    }
+
+    fn pdf(&self, hr: &HitRecord, wi: DVec3, wo: DVec3) -> f64 {
+        let distribution = self.distribution(hr);
+        let onb = hr.shading_frame();
+        let (wi, wo) = (onb.to_local(wi), onb.to_local(wo));
+        if distribution.is_smooth() || wi.z <= 0.0 || wo.z <= 0.0 {
+            return 0.0;
+        }
+        let wm = (wi + wo).normalize();
+        distribution.d_visible(wo, wm) / (4.0 * wo.dot(wm).abs())
+    }
+
+    fn dispersive(&self) -> bool {
+        self.thin_film.is_some()
+    }
+}
+
+impl Dielectric {
+    pub fn bk7() -> Self {
+        Self::new().dispersion(Dispersion::BK7)
+    }
+
+    pub fn diamond() -> Self {
+        Self::new().dispersion(Dispersion::DIAMOND)
+    }
+
+    /// Index of refraction for the ray's hero wavelength, or at the sodium D
+    /// line outside spectral mode.
+    fn ior(&self, hr: &HitRecord) -> f64 {
+        const SODIUM_D: f64 = 587.6;
+        let wavelength = hr.wavelength.unwrap_or(SODIUM_D);
+        self.dispersion.ior(wavelength).unwrap_or(self.ir)
+    }
+
+    /// Set the absorption so that light keeps `colour` of its intensity after
+    /// travelling `distance` through the interior.
+    pub fn transmittance_at_distance(self, colour: DVec3, distance: f64) -> Self {
+        let ln = |c: f64| c.max(1e-12).ln();
+        let colour = DVec3::new(ln(colour.x), ln(colour.y), ln(colour.z));
+        self.absorption(-colour / distance)
+    }
+
+    /// Beer-Lambert attenuation of a ray reaching the surface from inside.
+    /// Closed objects are assumed, so the whole path travelled by the ray
+    /// was through the interior.
+    fn interior_transmittance(&self, hr: &HitRecord) -> DVec3 {
+        if hr.front_face {
+            DVec3::ONE
+        } else {
+            // Rays have unit directions, so t is the distance travelled
+            (-self.absorption * hr.t).exp()
+        }
+    }
+
+    /// Index of refraction beyond the surface relative to the side the
+    /// normal faces.
+    fn eta(&self, hr: &HitRecord) -> f64 {
+        if hr.front_face {
+            self.ior(hr)
+        } else {
+            1.0 / self.ior(hr)
+        }
+    }
+
+    fn distribution(&self) -> TrowbridgeReitz {
+        TrowbridgeReitz::from_roughness(self.roughness, 0.0)
+    }
+
+    /// Reflectance of the surface for light arriving at `cos_theta` to the
+    /// normal, from below the surface if negative.
+    fn fresnel(&self, hr: &HitRecord, cos_theta: f64) -> DVec3 {
+        let Some(film) = self.thin_film else {
+            return DVec3::splat(microfacet::fresnel_dielectric(cos_theta, self.eta(hr)));
+        };
+        // The film is on the outside of the surface
+        let (near, far) = if hr.front_face == (cos_theta >= 0.0) {
+            (1.0, self.ior(hr))
+        } else {
+            (self.ior(hr), 1.0)
+        };
+        film.reflectance(
+            cos_theta.abs(),
+            near,
+            DVec3::splat(far),
+            DVec3::ZERO,
+            hr.wavelength,
+        )
+    }
+
+    fn sample_smooth(&self, hr: &HitRecord, wo: DVec3) -> BsdfSample {
+        let eta = self.eta(hr);
+        let r = self.fresnel(hr, wo.z);
+        let p_reflect = r.dot(DVec3::splat(1.0 / 3.0));
+        let transmitted = microfacet::transmitted_direction(wo, DVec3::Z, eta);
+        match transmitted {
+            Some(wi) if p_reflect <= random() => BsdfSample {
+                // Radiance is compressed into the smaller solid angle
+                value: (1.0 - r) / (eta * eta),
+                wi,
+                pdf: 1.0 - p_reflect,
+                lobe: Lobe::SPECULAR | Lobe::TRANSMISSION,
+            },
+            _ => BsdfSample {
+                value: r,
+                wi: DVec3::new(-wo.x, -wo.y, wo.z),
+                pdf: if transmitted.is_some() {
+                    p_reflect
+                } else {
+                    1.0
+                },
+                lobe: Lobe::SPECULAR | Lobe::REFLECTION,
+            },
+        }
+    }
+
+    /// Microfacet normal for a pair of local directions, facing +Z, or `None`
+    /// if the configuration is impossible.
+    fn half_vector(wi: DVec3, wo: DVec3, eta: f64) -> Option<DVec3> {
+        let reflect = wi.z > 0.0;
+        let wm = if reflect { wi + wo } else { wi * eta + wo };
+        if wi.z == 0.0 || vector::near_zero(wm) {
+            return None;
+        }
+        let wm = wm.normalize() * wm.z.signum();
+        // Discard back-facing microfacets
+        (wm.dot(wi) * wi.z >= 0.0 && wm.dot(wo) * wo.z >= 0.0).then_some(wm)
+    }
+
+    fn eval_local(&self, hr: &HitRecord, wi: DVec3, wo: DVec3) -> DVec3 {
+        let distribution = self.distribution();
+        let eta = self.eta(hr);
+        let Some(wm) = Self::half_vector(wi, wo, eta) else {
+            return DVec3::ZERO;
+        };
+        let f = self.fresnel(hr, wo.dot(wm));
+        let d = distribution.d(wm);
+        let g = distribution.g(wo, wi);
+        if wi.z > 0.0 {
+            d * g * f / (4.0 * wo.z)
+        } else {
+            let denom = (wi.dot(wm) + wo.dot(wm) / eta).powi(2) * wo.z;
+            d * (1.0 - f) * g * (wi.dot(wm) * wo.dot(wm) / denom).abs() / (eta * eta)
+        }
+    }
+
+    fn pdf_local(&self, hr: &HitRecord, wi: DVec3, wo: DVec3) -> f64 {
+        let distribution = self.distribution();
+        let eta = self.eta(hr);
+        let Some(wm) = Self::half_vector(wi, wo, eta) else {
+            return 0.0;
+        };
+        let r = self.fresnel(hr, wo.dot(wm)).dot(DVec3::splat(1.0 / 3.0));
+        if wi.z > 0.0 {
+            distribution.d_visible(wo, wm) / (4.0 * wo.dot(wm).abs()) * r
+        } else {
+            let denom = (wi.dot(wm) + wo.dot(wm) / eta).powi(2);
+            distribution.d_visible(wo, wm) * wi.dot(wm).abs() / denom * (1.0 - r)
+        }
     }
 }
 
 impl Material for Dielectric {
-    fn scatter(&self, r: &Ray, hr: &HitRecord) -> Option<(DVec3, Ray)> {
-        fn reflectance(cosine: f64, ref_idx: f64) -> f64 {
-            // Use Schlick's approximation for reflectance.
-            let r0 = (1.0 - ref_idx) / (1.0 + ref_idx);
-            let r0 = r0 * r0;
-            r0 + (1.0 - r0) * (1.0 - cosine).powi(5)
-        }
-
-        let refraction_ratio = self.ir.powi(if hr.front_face { -1 } else { 1 });
-        let unit_direction = r.direction.normalize();
-        let cos_theta = (-unit_direction).dot(hr.normal).min(1.0);
-        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
-
-        let cannot_refract = refraction_ratio * sin_theta > 1.0;
-        let direction = if cannot_refract || reflectance(cos_theta, refraction_ratio) > random() {
-            vector::reflect(unit_direction, hr.normal) // Must reflect
+    fn sample(&self, r: &Ray, hr: &HitRecord) -> Option<BsdfSample> {
+        let onb = Onb::new(hr.normal);
+        let wo = onb.to_local(-r.direction);
+        let eta = self.eta(hr);
+        let distribution = self.distribution();
+        if distribution.is_smooth() {
+            let sample = self.sample_smooth(hr, wo);
+            return Some(BsdfSample {
+                value: sample.value * self.interior_transmittance(hr),
+                wi: onb.to_world(sample.wi),
+                ..sample
+            });
+        }
+
+        let wm = distribution.sample_wm(wo);
+        let reflectance = self.fresnel(hr, wo.dot(wm)).dot(DVec3::splat(1.0 / 3.0));
+        let (wi, lobe) = match microfacet::transmitted_direction(wo, wm, eta) {
+            Some(wi) if reflectance <= random() => (wi, Lobe::GLOSSY | Lobe::TRANSMISSION),
+            _ => (vector::reflect(-wo, wm), Lobe::GLOSSY | Lobe::REFLECTION),
+        };
+        if lobe.is_transmission() == (wi.z > 0.0) {
+            return None;
+        }
+        let pdf = self.pdf_local(hr, wi, wo);
+        (pdf > 0.0).then(|| BsdfSample {
+            value: self.eval_local(hr, wi, wo) * self.interior_transmittance(hr),
+            wi: onb.to_world(wi),
+            pdf,
+            lobe,
+        })
+    }
+
+    fn eval(&self, hr: &HitRecord, wi: DVec3, wo: DVec3) -> DVec3 {
+        if self.distribution().is_smooth() {
+            return DVec3::ZERO;
+        }
+        let onb = Onb::new(hr.normal);
+        let value = self.eval_local(hr, onb.to_local(wi), onb.to_local(wo));
+        value * self.interior_transmittance(hr)
+    }
+
+    fn pdf(&self, hr: &HitRecord, wi: DVec3, wo: DVec3) -> f64 {
+        if self.distribution().is_smooth() {
+            return 0.0;
+        }
+        let onb = Onb::new(hr.normal);
+        self.pdf_local(hr, onb.to_local(wi), onb.to_local(wo))
+    }
+
+    fn dispersive(&self) -> bool {
+        self.dispersion != Dispersion::None || self.thin_film.is_some()
+    }
+}
+
+impl DiffuseLight {
+    fn cos_spread(&self) -> f64 {
+        self.spread.clamp(0.0, 90.0).to_radians().cos()
+    }
+
+    /// Power emitted per unit area and radiance, π for a one-sided diffuse
+    /// emitter, less for narrower spreads and twice as much from both sides.
+    pub fn exitance(&self) -> f64 {
+        let sides = if self.two_sided { 2.0 } else { 1.0 };
+        sides * PI * (1.0 - self.cos_spread().powi(2))
+    }
+}
+
+impl Material for DiffuseLight {
+    fn sample(&self, _r: &Ray, _hr: &HitRecord) -> Option<BsdfSample> {
+        None
+    }
+
+    fn emitted(&self, hr: &HitRecord, wo: DVec3) -> DVec3 {
+        let cos_theta = wo.normalize().dot(hr.geometric_normal);
+        if (hr.front_face || self.two_sided) && cos_theta >= self.cos_spread() {
+            self.emission.value(hr) * self.intensity
+        } else {
+            DVec3::ZERO
+        }
+    }
+
+    fn exitance(&self, _hr: &HitRecord) -> f64 {
+        DiffuseLight::exitance(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::hit::HitRecord;
+
+    use super::*;
+
+    #[test]
+    fn test_lambertian_sample_matches_eval() {
+        let material = Lambertian::new().albedo(DVec3::new(0.8, 0.4, 0.2));
+        let r = Ray::new(DVec3::new(0.0, 1.0, 0.0), DVec3::new(0.3, -1.0, 0.0));
+        let hr = HitRecord::new(&r, DVec3::ZERO, 1.0, DVec3::Y, &material);
+        for _ in 0..100 {
+            let s = material.sample(&r, &hr).expect("diffuse always scatters");
+            assert!(s.wi.dot(hr.normal) >= 0.0);
+            assert_eq!(s.lobe, Lobe::DIFFUSE | Lobe::REFLECTION);
+            assert!((s.pdf - material.pdf(&hr, s.wi, -r.direction)).abs() < 1e-12);
+            assert!(s.weight().abs_diff_eq(material.albedo.value(&hr), 1e-9));
+        }
+    }
+
+    #[test]
+    fn test_metal_anisotropy() {
+        // The highlight stretches along the U direction of the surface
+        let material = Metal::new().roughness(0.3).anisotropy(1.0);
+        let r = Ray::new(DVec3::new(0.0, 1.0, 0.0), DVec3::NEG_Y);
+        let hr = HitRecord::new(&r, DVec3::ZERO, 1.0, DVec3::Y, &material);
+        let along_x = DVec3::new(0.3, 1.0, 0.0).normalize();
+        let along_z = DVec3::new(0.0, 1.0, 0.3).normalize();
+        for (dpdu, stretched, narrow) in [
+            (DVec3::X, along_x, along_z),
+            (DVec3::new(0.0, 0.5, 2.0), along_z, along_x),
+        ] {
+            let hr = hr.with_derivatives(dpdu, DVec3::ZERO);
+            let f = |wi| material.eval(&hr, wi, DVec3::Y).x;
+            assert!(f(stretched) > 2.0 * f(narrow), "{dpdu}");
+        }
+    }
+
+    #[test]
+    fn test_dielectric_lobes() {
+        let material = Dielectric::new().ir(1.5);
+        let r = Ray::new(DVec3::new(0.0, 1.0, 0.0), DVec3::NEG_Y);
+        let hr = HitRecord::new(&r, DVec3::ZERO, 1.0, DVec3::Y, &material);
+        for _ in 0..100 {
+            let s = material.sample(&r, &hr).expect("glass always scatters");
+            assert!(s.lobe.is_specular());
+            let (expected_wi, expected_weight) = if s.lobe.is_transmission() {
+                (DVec3::NEG_Y, 1.0 / (1.5 * 1.5))
+            } else {
+                (DVec3::Y, 1.0)
+            };
+            assert!(s.weight().abs_diff_eq(DVec3::splat(expected_weight), 1e-12));
+            assert!(s.wi.abs_diff_eq(expected_wi, 1e-12));
+        }
+    }
+
+    #[test]
+    fn test_dispersion() {
+        let bk7 = Dispersion::BK7;
+        assert!((bk7.ior(587.6).unwrap() - 1.5168).abs() < 1e-4);
+        assert!(bk7.ior(450.0).unwrap() > bk7.ior(650.0).unwrap());
+        assert!((Dispersion::DIAMOND.ior(587.6).unwrap() - 2.417).abs() < 1e-3);
+        let cauchy = Dispersion::Cauchy { a: 1.5, b: 0.004 };
+        assert!((cauchy.ior(500.0).unwrap() - 1.516).abs() < 1e-12);
+        assert_eq!(Dispersion::None.ior(500.0), None);
+    }
+
+    #[test]
+    fn test_dielectric_absorption() {
+        let colour = DVec3::new(0.9, 0.5, 0.1);
+        let material = Dielectric::new().transmittance_at_distance(colour, 2.0);
+        let r = Ray::new(DVec3::ZERO, DVec3::Y);
+        // Leaving the glass after travelling 2 units through it
+        let hr = HitRecord::new(&r, DVec3::new(0.0, 2.0, 0.0), 2.0, DVec3::Y, &material);
+        let s = material.sample(&r, &hr).expect("glass always scatters");
+        let expected = if s.lobe.is_transmission() {
+            colour * 1.5 * 1.5
         } else {
-            vector::refract(unit_direction, hr.normal, refraction_ratio)
+            colour
         };
-        Some((DVec3::ONE, hr.ray(direction)))
+        assert!(s.weight().abs_diff_eq(expected, 1e-9));
+    }
+
+    #[test]
+    fn test_rough_dielectric_sample_matches_eval() {
+        let material = Dielectric::new()
+            .ir(1.5)
+            .roughness(0.5)
+            .absorption(DVec3::new(0.1, 0.2, 0.3));
+        for front_face in [true, false] {
+            let normal = if front_face { DVec3::Y } else { DVec3::NEG_Y };
+            let r = Ray::new(DVec3::new(0.0, 1.0, 0.0), DVec3::new(0.4, -1.0, 0.1));
+            let hr = HitRecord::new(&r, DVec3::ZERO, 1.0, normal, &material);
+            let (mut reflected, mut transmitted) = (0, 0);
+            for _ in 0..1000 {
+                let Some(s) = material.sample(&r, &hr) else {
+                    continue;
+                };
+                let wo = -r.direction;
+                assert!(!s.lobe.is_specular());
+                assert_eq!(s.lobe.is_transmission(), s.wi.dot(hr.normal) < 0.0);
+                assert!((s.pdf - material.pdf(&hr, s.wi, wo)).abs() < 1e-9 * s.pdf.max(1.0));
+                assert!(s.value.abs_diff_eq(material.eval(&hr, s.wi, wo), 1e-9));
+                if s.lobe.is_transmission() {
+                    transmitted += 1;
+                } else {
+                    reflected += 1;
+                }
+            }
+            assert!(reflected > 0 && transmitted > 0);
+        }
     }
 }
@@ -0,0 +1,429 @@
+use std::f64::consts::PI;
+
+use glam::DVec3;
+use rand::random;
+
+use crate::{ray::Ray, util::default_struct, vector::Onb};
+
+/// Henyey-Greenstein phase function. Positive `g` scatters forwards, negative
+/// `g` backwards.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct HenyeyGreenstein {
+    pub g: f64,
+}
+
+impl HenyeyGreenstein {
+    pub fn new(g: f64) -> Self {
+        Self { g }
+    }
+
+    /// Density of scattering by an angle with cosine `cos_theta` from the
+    /// direction of travel, per unit solid angle, as `sample` chooses it.
+    pub fn p(&self, cos_theta: f64) -> f64 {
+        let g = self.g;
+        let denom = 1.0 + g * g - 2.0 * g * cos_theta;
+        (1.0 - g * g) / (4.0 * PI * denom * denom.sqrt())
+    }
+
+    /// Scattered direction for light travelling along `direction`, with
+    /// density `p`.
+    pub fn sample(&self, direction: DVec3) -> DVec3 {
+        let g = self.g;
+        let u: f64 = random();
+        let cos_theta = if g.abs() < 1e-3 {
+            1.0 - 2.0 * u
+        } else {
+            let s = (1.0 - g * g) / (1.0 - g + 2.0 * g * u);
+            (1.0 + g * g - s * s) / (2.0 * g)
+        };
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * random::<f64>();
+        Onb::new(direction).to_world(DVec3::new(
+            sin_theta * phi.cos(),
+            sin_theta * phi.sin(),
+            cos_theta,
+        ))
+    }
+}
+
+/// Outcome of tracing a ray through a medium. `emission` is the radiance
+/// emitted by the medium along the way, reaching the start of the ray.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MediumEvent {
+    /// The ray scatters at distance `t`, into a direction sampled from
+    /// `phase`.
+    Scatter {
+        t: f64,
+        weight: DVec3,
+        emission: DVec3,
+        phase: HenyeyGreenstein,
+    },
+    /// The ray reaches the end of the segment without scattering.
+    Pass { weight: DVec3, emission: DVec3 },
+}
+
+/// Participating medium that rays travel through, such as the interior of a
+/// translucent object. Coefficients are per unit distance and per RGB
+/// channel.
+pub trait Medium: Send + Sync {
+    /// Sample where a ray scatters before `t_max`, or that it doesn't. The
+    /// throughput weight accounts for attenuation and the sampling density.
+    fn sample(&self, r: &Ray, t_max: f64) -> MediumEvent;
+
+    /// Fraction of light travelling along the ray for `t_max` that reaches
+    /// the end without being absorbed or scattered away. May be a noisy but
+    /// unbiased estimate.
+    fn transmittance(&self, r: &Ray, t_max: f64) -> DVec3;
+}
+
+default_struct!(
+    /// Medium with constant absorption and scattering coefficients.
+    #[derive(Copy)]
+    Homogeneous {
+        sigma_a: DVec3 = DVec3::ZERO,
+        sigma_s: DVec3 = DVec3::ONE,
+        g: f64 = 0.0,
+    }
+);
+
+impl Homogeneous {
+    /// Medium whose scattering events keep `albedo` of the light, with
+    /// `mean_free_path` between events.
+    pub fn from_albedo(albedo: DVec3, mean_free_path: DVec3) -> Self {
+        let sigma_t = 1.0 / mean_free_path;
+        Self::new()
+            .sigma_s(albedo * sigma_t)
+            .sigma_a((1.0 - albedo) * sigma_t)
+    }
+
+    /// Marble, per millimetre (Jensen et al. 2001).
+    pub fn marble() -> Self {
+        Self::new()
+            .sigma_a(DVec3::new(0.0021, 0.0041, 0.0071))
+            .sigma_s(DVec3::new(2.19, 2.62, 3.00))
+    }
+
+    /// Skin, per millimetre (Jensen et al. 2001).
+    pub fn skin() -> Self {
+        Self::new()
+            .sigma_a(DVec3::new(0.032, 0.17, 0.48))
+            .sigma_s(DVec3::new(0.74, 0.88, 1.01))
+    }
+
+    /// Whole milk, per millimetre (Jensen et al. 2001).
+    pub fn milk() -> Self {
+        Self::new()
+            .sigma_a(DVec3::new(0.0011, 0.0024, 0.014))
+            .sigma_s(DVec3::new(2.55, 3.21, 3.77))
+    }
+
+    /// Scale the coefficients, such as by the number of millimetres per scene
+    /// unit for the measured presets.
+    pub fn scaled(self, factor: f64) -> Self {
+        Self {
+            sigma_a: self.sigma_a * factor,
+            sigma_s: self.sigma_s * factor,
+            ..self
+        }
+    }
+
+    pub fn sigma_t(&self) -> DVec3 {
+        self.sigma_a + self.sigma_s
+    }
+}
+
+impl Medium for Homogeneous {
+    fn sample(&self, _r: &Ray, t_max: f64) -> MediumEvent {
+        // Sample distances for a random channel, weighting by the density
+        // averaged over all channels
+        let sigma_t = self.sigma_t();
+        let channel = ((random::<f64>() * 3.0) as usize).min(2);
+        let t = -(1.0 - random::<f64>()).ln() / sigma_t[channel];
+        if t < t_max {
+            let transmittance = (-sigma_t * t).exp();
+            let pdf = (sigma_t * transmittance).dot(DVec3::splat(1.0 / 3.0));
+            MediumEvent::Scatter {
+                t,
+                weight: self.sigma_s * transmittance / pdf,
+                emission: DVec3::ZERO,
+                phase: HenyeyGreenstein::new(self.g),
+            }
+        } else {
+            let transmittance = (-sigma_t * t_max).exp();
+            let probability = transmittance.dot(DVec3::splat(1.0 / 3.0));
+            MediumEvent::Pass {
+                weight: if probability > 0.0 {
+                    transmittance / probability
+                } else {
+                    DVec3::ZERO
+                },
+                emission: DVec3::ZERO,
+            }
+        }
+    }
+
+    fn transmittance(&self, _r: &Ray, t_max: f64) -> DVec3 {
+        (-self.sigma_t() * t_max).exp()
+    }
+}
+
+default_struct!(
+    /// Fog or haze thinning out exponentially with altitude, for aerial
+    /// perspective in outdoor scenes. The density is `density` at `height`
+    /// and falls by a factor of e every `1 / falloff` units above it.
+    /// Extinction is the same for every channel.
+    #[derive(Copy)]
+    HeightFog {
+        density: f64 = 0.05,
+        falloff: f64 = 0.5,
+        height: f64 = 0.0,
+        albedo: DVec3 = DVec3::ONE,
+        g: f64 = 0.0,
+    }
+);
+
+impl HeightFog {
+    fn density_at(&self, p: DVec3) -> f64 {
+        self.density * (-self.falloff * (p.y - self.height)).exp()
+    }
+
+    /// Optical depth along the ray up to `t`. The density changes by a
+    /// factor of `exp(-rate * t)` along the ray.
+    fn optical_depth(&self, r: &Ray, t: f64) -> f64 {
+        let (sigma_0, rate) = (self.density_at(r.origin), self.falloff * r.direction.y);
+        if sigma_0 <= 0.0 {
+            0.0
+        } else if rate.abs() < 1e-9 {
+            sigma_0 * t
+        } else {
+            // Rays escaping upwards cross a finite amount of fog
+            sigma_0 * (1.0 - (-rate * t).exp()) / rate
+        }
+    }
+}
+
+impl Medium for HeightFog {
+    fn sample(&self, r: &Ray, t_max: f64) -> MediumEvent {
+        // Invert the optical depth for an exponentially distributed depth
+        let depth = -(1.0 - random::<f64>()).ln();
+        let (sigma_0, rate) = (self.density_at(r.origin), self.falloff * r.direction.y);
+        let t = if rate.abs() < 1e-9 {
+            depth / sigma_0
+        } else {
+            let x = 1.0 - depth * rate / sigma_0;
+            if x > 0.0 {
+                -x.ln() / rate
+            } else {
+                f64::INFINITY
+            }
+        };
+        if t < t_max {
+            MediumEvent::Scatter {
+                t,
+                weight: self.albedo,
+                emission: DVec3::ZERO,
+                phase: HenyeyGreenstein::new(self.g),
+            }
+        } else {
+            MediumEvent::Pass {
+                weight: DVec3::ONE,
+                emission: DVec3::ZERO,
+            }
+        }
+    }
+
+    fn transmittance(&self, r: &Ray, t_max: f64) -> DVec3 {
+        DVec3::splat((-self.optical_depth(r, t_max)).exp())
+    }
+}
+
+default_struct!(
+    /// Haze of constant density below the height `ceiling` and clear air
+    /// above it, for outdoor scenes whose sky should stay visible above the
+    /// haze. Extinction is `density` per unit distance for every channel.
+    #[derive(Copy)]
+    Haze {
+        density: f64 = 0.05,
+        ceiling: f64 = 10.0,
+        albedo: DVec3 = DVec3::ONE,
+        g: f64 = 0.0,
+    }
+);
+
+impl Haze {
+    /// Part of the ray up to `t_max` below the ceiling.
+    fn segment(&self, r: &Ray, t_max: f64) -> Option<(f64, f64)> {
+        let t_ceiling = (self.ceiling - r.origin.y) / r.direction.y;
+        let (t_min, t_end) = if r.origin.y < self.ceiling {
+            let t_end = if r.direction.y > 0.0 {
+                t_ceiling
+            } else {
+                f64::INFINITY
+            };
+            (0.0, t_end)
+        } else if r.direction.y < 0.0 {
+            (t_ceiling, f64::INFINITY)
+        } else {
+            return None;
+        };
+        let t_end = t_end.min(t_max);
+        (t_min < t_end).then_some((t_min, t_end))
+    }
+}
+
+impl Medium for Haze {
+    fn sample(&self, r: &Ray, t_max: f64) -> MediumEvent {
+        let pass = MediumEvent::Pass {
+            weight: DVec3::ONE,
+            emission: DVec3::ZERO,
+        };
+        let Some((t_min, t_end)) = self.segment(r, t_max) else {
+            return pass;
+        };
+        let t = t_min - (1.0 - random::<f64>()).ln() / self.density;
+        if t < t_end {
+            MediumEvent::Scatter {
+                t,
+                weight: self.albedo,
+                emission: DVec3::ZERO,
+                phase: HenyeyGreenstein::new(self.g),
+            }
+        } else {
+            pass
+        }
+    }
+
+    fn transmittance(&self, r: &Ray, t_max: f64) -> DVec3 {
+        let depth = self
+            .segment(r, t_max)
+            .map_or(0.0, |(t_min, t_end)| self.density * (t_end - t_min));
+        DVec3::splat((-depth).exp())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_henyey_greenstein() {
+        // Sampled directions, binned by their cosine with the direction of
+        // travel, follow the density
+        let direction = DVec3::new(0.2, -0.5, 0.8).normalize();
+        let bins = 20;
+        let width = 2.0 / bins as f64;
+        for g in [0.0, 0.7, -0.7] {
+            let phase = HenyeyGreenstein::new(g);
+            let n = 200_000;
+            let mut counts = vec![0; bins];
+            for _ in 0..n {
+                let cos_theta = phase.sample(direction).dot(direction);
+                let bin = ((cos_theta + 1.0) / width) as usize;
+                counts[bin.min(bins - 1)] += 1;
+            }
+            let mut total = 0.0;
+            for (bin, &count) in counts.iter().enumerate() {
+                // Solid angle is 2π per unit of the cosine
+                let steps = 100;
+                let expected: f64 = (0..steps)
+                    .map(|i| {
+                        let cos_theta =
+                            -1.0 + (bin as f64 + (i as f64 + 0.5) / steps as f64) * width;
+                        2.0 * PI * phase.p(cos_theta) * width / steps as f64
+                    })
+                    .sum();
+                total += expected;
+                let observed = count as f64 / n as f64;
+                assert!(
+                    (observed - expected).abs() < 0.005 + 0.05 * expected,
+                    "{g} bin {bin}: {observed} {expected}"
+                );
+            }
+            assert!((total - 1.0).abs() < 1e-3, "{g}: {total}");
+        }
+    }
+
+    #[test]
+    fn test_homogeneous_transmittance() {
+        let medium = Homogeneous::new()
+            .sigma_a(DVec3::new(0.5, 1.0, 2.0))
+            .sigma_s(DVec3::new(0.5, 0.0, 1.0));
+        let r = Ray::new(DVec3::ZERO, DVec3::Z);
+        let n = 100_000;
+        // Unscattered light is attenuated by Beer-Lambert on average
+        let passed: DVec3 = (0..n)
+            .map(|_| match medium.sample(&r, 0.5) {
+                MediumEvent::Pass { weight, .. } => weight,
+                MediumEvent::Scatter { .. } => DVec3::ZERO,
+            })
+            .sum::<DVec3>()
+            / n as f64;
+        let expected = (-medium.sigma_t() * 0.5).exp();
+        assert!(passed.abs_diff_eq(expected, 0.01), "{passed} {expected}");
+        // Light scattered on the way is the rest, times the albedo
+        let scattered: DVec3 = (0..n)
+            .map(|_| match medium.sample(&r, 0.5) {
+                MediumEvent::Scatter { weight, .. } => weight,
+                MediumEvent::Pass { .. } => DVec3::ZERO,
+            })
+            .sum::<DVec3>()
+            / n as f64;
+        let expected = (1.0 - expected) * medium.sigma_s / medium.sigma_t();
+        assert!(
+            scattered.abs_diff_eq(expected, 0.01),
+            "{scattered} {expected}"
+        );
+    }
+
+    #[test]
+    fn test_height_fog() {
+        let fog = HeightFog::new().density(0.5).falloff(0.8).height(1.0);
+        let n = 20_000;
+        for (origin, direction, t_max) in [
+            (DVec3::ZERO, DVec3::new(1.0, 0.3, 0.0), 4.0),
+            (DVec3::new(0.0, 2.0, 0.0), DVec3::new(0.2, -1.0, 0.0), 1.5),
+            (DVec3::ZERO, DVec3::X, 2.0),
+            (DVec3::ZERO, DVec3::Y, f64::INFINITY),
+        ] {
+            let r = Ray::new(origin, direction);
+            // Sampled distances agree with the transmittance
+            let passed = (0..n)
+                .filter(|_| matches!(fog.sample(&r, t_max), MediumEvent::Pass { .. }))
+                .count();
+            let expected = fog.transmittance(&r, t_max).x;
+            assert!((passed as f64 / n as f64 - expected).abs() < 0.01);
+        }
+        // Straight up, the whole column of fog above the origin is crossed
+        let r = Ray::new(DVec3::ZERO, DVec3::Y);
+        let expected = (-0.5 * 0.8f64.exp() / 0.8).exp();
+        assert!((fog.transmittance(&r, f64::INFINITY).x - expected).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_haze() {
+        let haze = Haze::new().density(0.5).ceiling(2.0);
+        let n = 20_000;
+        for (origin, direction, t_max) in [
+            (DVec3::ZERO, DVec3::new(1.0, 0.5, 0.0), f64::INFINITY),
+            (DVec3::new(0.0, 3.0, 0.0), DVec3::new(1.0, -1.0, 0.0), 4.0),
+            (DVec3::ZERO, DVec3::X, 2.0),
+        ] {
+            let r = Ray::new(origin, direction);
+            // Sampled distances agree with the transmittance
+            let passed = (0..n)
+                .filter(|_| matches!(haze.sample(&r, t_max), MediumEvent::Pass { .. }))
+                .count();
+            let expected = haze.transmittance(&r, t_max).x;
+            assert!((passed as f64 / n as f64 - expected).abs() < 0.01);
+        }
+        // The sky stays visible through the haze up to the ceiling, but not
+        // along the ground
+        let up = Ray::new(DVec3::ZERO, DVec3::Y);
+        let expected = (-0.5 * 2.0f64).exp();
+        assert!((haze.transmittance(&up, f64::INFINITY).x - expected).abs() < 1e-12);
+        let along = Ray::new(DVec3::ZERO, DVec3::X);
+        assert_eq!(haze.transmittance(&along, f64::INFINITY), DVec3::ZERO);
+        let above = Ray::new(DVec3::new(0.0, 3.0, 0.0), DVec3::X);
+        assert_eq!(haze.transmittance(&above, f64::INFINITY), DVec3::ONE);
+    }
+}
@@ -0,0 +1,235 @@
+use std::f64::consts::PI;
+
+use glam::DVec3;
+use rand::random;
+
+/// Trowbridge-Reitz (GGX) distribution of microfacet normals, with Smith
+/// masking-shadowing. Directions are in a local frame where +Z is the
+/// macrosurface normal.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct TrowbridgeReitz {
+    pub alpha_x: f64,
+    pub alpha_y: f64,
+}
+
+impl TrowbridgeReitz {
+    pub fn new(alpha_x: f64, alpha_y: f64) -> Self {
+        Self { alpha_x, alpha_y }
+    }
+
+    /// Distribution for a perceptual roughness in [0, 1], stretched along X
+    /// by an anisotropy in [0, 1].
+    pub fn from_roughness(roughness: f64, anisotropy: f64) -> Self {
+        let alpha = roughness * roughness;
+        let aspect = (1.0 - 0.9 * anisotropy).sqrt();
+        Self::new(alpha / aspect, alpha * aspect)
+    }
+
+    /// Below this roughness the distribution is treated as a perfect mirror.
+    pub fn is_smooth(&self) -> bool {
+        self.alpha_x.max(self.alpha_y) < 1e-3
+    }
+
+    pub fn d(&self, wm: DVec3) -> f64 {
+        let cos2_theta = wm.z * wm.z;
+        if cos2_theta <= 0.0 {
+            return 0.0;
+        }
+        let e = ((wm.x / self.alpha_x).powi(2) + (wm.y / self.alpha_y).powi(2)) / cos2_theta;
+        1.0 / (PI * self.alpha_x * self.alpha_y * cos2_theta * cos2_theta * (1.0 + e).powi(2))
+    }
+
+    fn lambda(&self, w: DVec3) -> f64 {
+        let cos2_theta = w.z * w.z;
+        if cos2_theta <= 0.0 {
+            return f64::INFINITY;
+        }
+        let alpha2_tan2_theta =
+            ((w.x * self.alpha_x).powi(2) + (w.y * self.alpha_y).powi(2)) / cos2_theta;
+        ((1.0 + alpha2_tan2_theta).sqrt() - 1.0) / 2.0
+    }
+
+    pub fn g1(&self, w: DVec3) -> f64 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    /// Height-correlated masking-shadowing.
+    pub fn g(&self, wo: DVec3, wi: DVec3) -> f64 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /// Density of microfacet normals visible from `w`.
+    pub fn d_visible(&self, w: DVec3, wm: DVec3) -> f64 {
+        self.g1(w) / w.z.abs() * self.d(wm) * w.dot(wm).max(0.0)
+    }
+
+    /// Sample a microfacet normal visible from `w` (Heitz 2018), with density
+    /// `d_visible`.
+    pub fn sample_wm(&self, w: DVec3) -> DVec3 {
+        // Transform the view direction to the hemisphere configuration
+        let mut wh = DVec3::new(self.alpha_x * w.x, self.alpha_y * w.y, w.z).normalize();
+        if wh.z < 0.0 {
+            wh = -wh;
+        }
+        let len2 = wh.x * wh.x + wh.y * wh.y;
+        let t1 = if len2 > 0.0 {
+            DVec3::new(-wh.y, wh.x, 0.0) / len2.sqrt()
+        } else {
+            DVec3::X
+        };
+        let t2 = wh.cross(t1);
+
+        // Sample the projected area of the visible hemisphere
+        let r = random::<f64>().sqrt();
+        let phi = 2.0 * PI * random::<f64>();
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + wh.z);
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * r * phi.sin();
+        let p3 = (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt();
+        let nh = p1 * t1 + p2 * t2 + p3 * wh;
+
+        // Transform back to the ellipsoid configuration
+        DVec3::new(self.alpha_x * nh.x, self.alpha_y * nh.y, nh.z.max(1e-6)).normalize()
+    }
+}
+
+/// Unpolarised Fresnel reflectance of a conductor with complex index of
+/// refraction `eta + ik` relative to the outside medium.
+pub fn fresnel_conductor(cos_theta_i: f64, eta: DVec3, k: DVec3) -> DVec3 {
+    let channel = |eta: f64, k: f64| {
+        let cos2 = cos_theta_i * cos_theta_i;
+        let sin2 = 1.0 - cos2;
+        let t0 = eta * eta - k * k - sin2;
+        let a2_plus_b2 = (t0 * t0 + 4.0 * eta * eta * k * k).sqrt();
+        let t1 = a2_plus_b2 + cos2;
+        let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+        let t2 = 2.0 * cos_theta_i * a;
+        let rs = (t1 - t2) / (t1 + t2);
+        let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+        let t4 = t2 * sin2;
+        let rp = rs * (t3 - t4) / (t3 + t4);
+        0.5 * (rp + rs)
+    };
+    DVec3::new(
+        channel(eta.x, k.x),
+        channel(eta.y, k.y),
+        channel(eta.z, k.z),
+    )
+}
+
+/// Unpolarised Fresnel reflectance of a dielectric interface, where `eta` is
+/// the index of refraction on the far side of the normal relative to the near
+/// side.
+pub fn fresnel_dielectric(cos_theta_i: f64, eta: f64) -> f64 {
+    let (cos_theta_i, eta) = if cos_theta_i < 0.0 {
+        (-cos_theta_i, 1.0 / eta)
+    } else {
+        (cos_theta_i, eta)
+    };
+    let cos_theta_i = cos_theta_i.min(1.0);
+    let sin2_theta_t = (1.0 - cos_theta_i * cos_theta_i) / (eta * eta);
+    if sin2_theta_t >= 1.0 {
+        return 1.0; // Total internal reflection
+    }
+    let cos_theta_t = (1.0 - sin2_theta_t).sqrt();
+    let r_parallel = (eta * cos_theta_i - cos_theta_t) / (eta * cos_theta_i + cos_theta_t);
+    let r_perpendicular = (cos_theta_i - eta * cos_theta_t) / (cos_theta_i + eta * cos_theta_t);
+    (r_parallel * r_parallel + r_perpendicular * r_perpendicular) / 2.0
+}
+
+/// Direction transmitted through a (micro)surface with normal `n` from `w`,
+/// which points away from the surface on the same side as `n`. `eta` is the
+/// relative index of refraction as in `fresnel_dielectric`.
+pub fn transmitted_direction(w: DVec3, n: DVec3, eta: f64) -> Option<DVec3> {
+    let cos_theta_i = w.dot(n);
+    let sin2_theta_t = (1.0 - cos_theta_i * cos_theta_i).max(0.0) / (eta * eta);
+    if sin2_theta_t >= 1.0 {
+        return None;
+    }
+    let cos_theta_t = (1.0 - sin2_theta_t).sqrt();
+    Some(-w / eta + (cos_theta_i / eta - cos_theta_t) * n)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::vector;
+
+    use super::*;
+
+    #[test]
+    fn test_distribution_normalised() {
+        // The projected area of the microfacets is the macrosurface area
+        for distribution in [
+            TrowbridgeReitz::from_roughness(0.5, 0.0),
+            TrowbridgeReitz::from_roughness(0.8, 0.7),
+        ] {
+            let n = 200_000;
+            let total: f64 = (0..n)
+                .map(|_| {
+                    let wm = vector::random_cosine_direction();
+                    distribution.d(wm) * wm.z / vector::cosine_hemisphere_pdf(wm.z)
+                })
+                .sum();
+            assert!((total / n as f64 - 1.0).abs() < 0.02);
+        }
+    }
+
+    #[test]
+    fn test_visible_normals_normalised() {
+        let distribution = TrowbridgeReitz::from_roughness(0.6, 0.3);
+        let w = DVec3::new(0.5, 0.2, 0.7).normalize();
+        let n = 200_000;
+        let total: f64 = (0..n)
+            .map(|_| {
+                let wm = vector::random_cosine_direction();
+                distribution.d_visible(w, wm) / vector::cosine_hemisphere_pdf(wm.z)
+            })
+            .sum();
+        assert!((total / n as f64 - 1.0).abs() < 0.02);
+
+        // Sampled normals have the same mean as the density
+        let expected: DVec3 = (0..n)
+            .map(|_| {
+                let wm = vector::random_cosine_direction();
+                wm * distribution.d_visible(w, wm) / vector::cosine_hemisphere_pdf(wm.z)
+            })
+            .sum();
+        let sampled: DVec3 = (0..n)
+            .map(|_| {
+                let wm = distribution.sample_wm(w);
+                assert!(wm.z > 0.0 && w.dot(wm) >= 0.0);
+                wm
+            })
+            .sum();
+        assert!((expected / n as f64).abs_diff_eq(sampled / n as f64, 0.01));
+    }
+
+    #[test]
+    fn test_fresnel_conductor() {
+        let eta = DVec3::splat(0.2);
+        let k = DVec3::splat(3.0);
+        // Reflectance at normal incidence is ((n - 1)^2 + k^2) / ((n + 1)^2 + k^2)
+        let f0 = (0.8 * 0.8 + 9.0) / (1.2 * 1.2 + 9.0);
+        assert!(fresnel_conductor(1.0, eta, k).abs_diff_eq(DVec3::splat(f0), 1e-9));
+        assert!(fresnel_conductor(0.0, eta, k).abs_diff_eq(DVec3::ONE, 1e-9));
+    }
+
+    #[test]
+    fn test_fresnel_dielectric() {
+        assert!((fresnel_dielectric(1.0, 1.5) - 0.04).abs() < 1e-9);
+        assert!((fresnel_dielectric(-1.0, 1.5) - 0.04).abs() < 1e-9);
+        assert_eq!(fresnel_dielectric(0.5, 1.0 / 1.5), 1.0);
+        assert!((fresnel_dielectric(1e-9, 1.5) - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_transmitted_direction() {
+        let w = DVec3::new(-0.6, 0.0, 0.8);
+        let t = transmitted_direction(w, DVec3::Z, 1.5).expect("enters the denser side");
+        assert!((t.length() - 1.0).abs() < 1e-12);
+        // Snell's law
+        assert!((0.6 - 1.5 * t.x).abs() < 1e-12);
+        assert!(t.z < 0.0);
+        assert!(transmitted_direction(w, DVec3::Z, 0.5).is_none());
+    }
+}
@@ -0,0 +1,280 @@
+use std::sync::Arc;
+
+use glam::{DVec2, DVec3};
+
+use crate::{
+    hit::HitRecord,
+    material::{BsdfSample, Material},
+    medium::Medium,
+    ray::Ray,
+    texture::TextureRef,
+    vector::Onb,
+};
+
+/// Step in UV space for finite differences of height textures.
+const BUMP_DELTA: f64 = 1e-3;
+
+/// Tangents along `dpdu` and `dpdv`, falling back to an arbitrary frame
+/// around the normal where the surface doesn't provide derivatives.
+fn tangents(hr: &HitRecord, n: DVec3) -> (DVec3, DVec3) {
+    if hr.dpdu.cross(hr.dpdv).length_squared() > 0.0 {
+        (hr.dpdu, hr.dpdv)
+    } else {
+        let onb = Onb::new(n);
+        (onb.u, onb.v)
+    }
+}
+
+/// Replace the shading normal of `hr` by `outward`, a normal on the outside of
+/// the surface, keeping it in front of `wo`.
+fn with_shading_normal<'a>(hr: &HitRecord<'a>, outward: DVec3, wo: DVec3) -> HitRecord<'a> {
+    let mut n = if hr.front_face { outward } else { -outward };
+    // Tilt normals facing away from the viewer back towards them
+    let cos = n.dot(wo);
+    if cos < 1e-3 {
+        n = (n + (1e-3 - cos) * wo).normalize();
+    }
+    HitRecord { normal: n, ..*hr }
+}
+
+/// Whether `wi` is on the same side of the geometric and the shading
+/// surfaces, so scattering doesn't leak light through the geometry.
+fn consistent(hr: &HitRecord, wi: DVec3) -> bool {
+    (wi.dot(hr.geometric_normal) > 0.0) == (wi.dot(hr.normal) > 0.0)
+}
+
+fn sample(material: &dyn Material, r: &Ray, hr: &HitRecord) -> Option<BsdfSample> {
+    let s = material.sample(r, hr)?;
+    consistent(hr, s.wi).then_some(s)
+}
+
+fn eval(material: &dyn Material, hr: &HitRecord, wi: DVec3, wo: DVec3) -> DVec3 {
+    if consistent(hr, wi) {
+        material.eval(hr, wi, wo)
+    } else {
+        DVec3::ZERO
+    }
+}
+
+fn pdf(material: &dyn Material, hr: &HitRecord, wi: DVec3, wo: DVec3) -> f64 {
+    if consistent(hr, wi) {
+        material.pdf(hr, wi, wo)
+    } else {
+        0.0
+    }
+}
+
+/// Perturbs the shading normal of a material with a tangent-space normal map,
+/// where red and green encode the offsets along the U and V tangents and blue
+/// the component along the normal.
+pub struct NormalMap {
+    material: Arc<dyn Material>,
+    map: TextureRef,
+    strength: f64,
+}
+
+impl NormalMap {
+    pub fn new(material: Arc<dyn Material>, map: impl Into<TextureRef>) -> Self {
+        Self {
+            material,
+            map: map.into(),
+            strength: 1.0,
+        }
+    }
+
+    /// Scale the tangential offsets of the map.
+    pub fn strength(self, strength: f64) -> Self {
+        Self { strength, ..self }
+    }
+
+    fn perturb<'a>(&self, hr: &HitRecord<'a>, wo: DVec3) -> HitRecord<'a> {
+        let n = hr.outward_normal();
+        let (dpdu, dpdv) = tangents(hr, n);
+        let t = (dpdu - n * n.dot(dpdu)).normalize();
+        let mut b = n.cross(t);
+        if b.dot(dpdv) < 0.0 {
+            b = -b;
+        }
+        let offset = 2.0 * self.map.value(hr) - 1.0;
+        let outward = (self.strength * (offset.x * t + offset.y * b) + offset.z * n)
+            .try_normalize()
+            .unwrap_or(n);
+        with_shading_normal(hr, outward, wo)
+    }
+}
+
+impl Material for NormalMap {
+    fn sample(&self, r: &Ray, hr: &HitRecord) -> Option<BsdfSample> {
+        sample(&*self.material, r, &self.perturb(hr, -r.direction))
+    }
+
+    fn eval(&self, hr: &HitRecord, wi: DVec3, wo: DVec3) -> DVec3 {
+        eval(&*self.material, &self.perturb(hr, wo), wi, wo)
+    }
+
+    fn pdf(&self, hr: &HitRecord, wi: DVec3, wo: DVec3) -> f64 {
+        pdf(&*self.material, &self.perturb(hr, wo), wi, wo)
+    }
+
+    fn dispersive(&self) -> bool {
+        self.material.dispersive()
+    }
+
+    fn alpha(&self, hr: &HitRecord) -> f64 {
+        self.material.alpha(hr)
+    }
+
+    fn interior(&self) -> Option<&dyn Medium> {
+        self.material.interior()
+    }
+
+    fn is_interface(&self) -> bool {
+        self.material.is_interface()
+    }
+
+    fn emitted(&self, hr: &HitRecord, wo: DVec3) -> DVec3 {
+        self.material.emitted(hr, wo)
+    }
+
+    fn exitance(&self, hr: &HitRecord) -> f64 {
+        self.material.exitance(hr)
+    }
+}
+
+/// Perturbs the shading normal of a material as if the surface were displaced
+/// outwards by a height texture, scaled by `scale` into world units.
+pub struct BumpMap {
+    material: Arc<dyn Material>,
+    height: TextureRef,
+    scale: f64,
+}
+
+impl BumpMap {
+    pub fn new(material: Arc<dyn Material>, height: impl Into<TextureRef>) -> Self {
+        Self {
+            material,
+            height: height.into(),
+            scale: 1.0,
+        }
+    }
+
+    pub fn scale(self, scale: f64) -> Self {
+        Self { scale, ..self }
+    }
+
+    fn perturb<'a>(&self, hr: &HitRecord<'a>, wo: DVec3) -> HitRecord<'a> {
+        let n = hr.outward_normal();
+        let (dpdu, dpdv) = tangents(hr, n);
+        let height = |uv: DVec2, p: DVec3| self.scale * self.height.0.value(uv, p).x;
+        let h = height(hr.uv, hr.p);
+        let dhdu =
+            (height(hr.uv + DVec2::X * BUMP_DELTA, hr.p + BUMP_DELTA * dpdu) - h) / BUMP_DELTA;
+        let dhdv =
+            (height(hr.uv + DVec2::Y * BUMP_DELTA, hr.p + BUMP_DELTA * dpdv) - h) / BUMP_DELTA;
+        // The change in the normal over the surface is neglected
+        let mut outward = (dpdu + dhdu * n)
+            .cross(dpdv + dhdv * n)
+            .try_normalize()
+            .unwrap_or(n);
+        if outward.dot(n) < 0.0 {
+            outward = -outward;
+        }
+        with_shading_normal(hr, outward, wo)
+    }
+}
+
+impl Material for BumpMap {
+    fn sample(&self, r: &Ray, hr: &HitRecord) -> Option<BsdfSample> {
+        sample(&*self.material, r, &self.perturb(hr, -r.direction))
+    }
+
+    fn eval(&self, hr: &HitRecord, wi: DVec3, wo: DVec3) -> DVec3 {
+        eval(&*self.material, &self.perturb(hr, wo), wi, wo)
+    }
+
+    fn pdf(&self, hr: &HitRecord, wi: DVec3, wo: DVec3) -> f64 {
+        pdf(&*self.material, &self.perturb(hr, wo), wi, wo)
+    }
+
+    fn dispersive(&self) -> bool {
+        self.material.dispersive()
+    }
+
+    fn alpha(&self, hr: &HitRecord) -> f64 {
+        self.material.alpha(hr)
+    }
+
+    fn interior(&self) -> Option<&dyn Medium> {
+        self.material.interior()
+    }
+
+    fn is_interface(&self) -> bool {
+        self.material.is_interface()
+    }
+
+    fn emitted(&self, hr: &HitRecord, wo: DVec3) -> DVec3 {
+        self.material.emitted(hr, wo)
+    }
+
+    fn exitance(&self, hr: &HitRecord) -> f64 {
+        self.material.exitance(hr)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{material::Lambertian, texture::Texture};
+
+    use super::*;
+
+    /// Height increasing along U.
+    struct Ramp;
+
+    impl Texture for Ramp {
+        fn value(&self, uv: DVec2, _p: DVec3) -> DVec3 {
+            DVec3::splat(uv.x)
+        }
+    }
+
+    fn hit_record(material: &dyn Material) -> HitRecord<'_> {
+        let r = Ray::new(DVec3::new(0.0, 0.0, 1.0), DVec3::NEG_Z);
+        HitRecord::new(&r, DVec3::ZERO, 1.0, DVec3::Z, material)
+            .with_derivatives(DVec3::X, DVec3::Y)
+    }
+
+    #[test]
+    fn test_flat_normal_map() {
+        let lambertian = Arc::new(Lambertian::new());
+        let material = NormalMap::new(lambertian, DVec3::new(0.5, 0.5, 1.0));
+        let hr = hit_record(&material);
+        assert!(material
+            .perturb(&hr, DVec3::Z)
+            .normal
+            .abs_diff_eq(DVec3::Z, 1e-12));
+
+        let material = NormalMap::new(Arc::new(Lambertian::new()), DVec3::new(1.0, 0.5, 1.0));
+        let normal = material.perturb(&hr, DVec3::Z).normal;
+        assert!(normal.abs_diff_eq(DVec3::new(1.0, 0.0, 1.0).normalize(), 1e-12));
+    }
+
+    #[test]
+    fn test_bump_map() {
+        let material = BumpMap::new(Arc::new(Lambertian::new()), Arc::new(Ramp)).scale(0.5);
+        let hr = hit_record(&material);
+        // Rising to +U tilts the normal towards -U
+        let normal = material.perturb(&hr, DVec3::Z).normal;
+        assert!(normal.abs_diff_eq(DVec3::new(-0.5, 0.0, 1.0).normalize(), 1e-9));
+    }
+
+    #[test]
+    fn test_no_light_leaks() {
+        let material = NormalMap::new(Arc::new(Lambertian::new()), DVec3::new(1.0, 0.5, 0.6));
+        let hr = hit_record(&material);
+        let r = Ray::new(DVec3::new(0.0, 0.0, 1.0), DVec3::NEG_Z);
+        for _ in 0..1000 {
+            if let Some(s) = material.sample(&r, &hr) {
+                assert!(s.wi.dot(hr.geometric_normal) > 0.0);
+            }
+        }
+    }
+}
@@ -0,0 +1,383 @@
+use std::f64::consts::PI;
+
+use glam::DVec3;
+use rand::random;
+
+use crate::{
+    hit::HitRecord,
+    material::{BsdfSample, Dielectric, Lobe, Material},
+    microfacet::TrowbridgeReitz,
+    ray::Ray,
+    texture::TextureRef,
+    util::default_struct,
+    vector,
+};
+
+default_struct!(
+    /// Uber material after the Disney principled BSDF, with parameters
+    /// matching glTF and most content creation tools. Layers a diffuse base
+    /// with sheen, a GGX specular lobe, a clearcoat and rough glass
+    /// transmission tinted by `base_colour`. `anisotropy` stretches the
+    /// specular highlight along `dpdu`, the U direction of the surface.
+    Principled {
+        #[into]
+        base_colour: TextureRef = DVec3::splat(0.8).into(),
+        #[into]
+        metallic: TextureRef = 0.0.into(),
+        #[into]
+        roughness: TextureRef = 0.5.into(),
+        anisotropy: f64 = 0.0,
+        specular: f64 = 0.5,
+        specular_tint: f64 = 0.0,
+        sheen: f64 = 0.0,
+        sheen_tint: f64 = 0.5,
+        clearcoat: f64 = 0.0,
+        clearcoat_roughness: f64 = 0.03,
+        transmission: f64 = 0.0,
+        ior: f64 = 1.5,
+    }
+);
+
+/// Parameters of a `Principled` material at a point on a surface.
+#[derive(Clone, Copy)]
+struct Bsdf {
+    base_colour: DVec3,
+    metallic: f64,
+    roughness: f64,
+    anisotropy: f64,
+    specular: f64,
+    specular_tint: f64,
+    sheen: f64,
+    sheen_tint: f64,
+    clearcoat: f64,
+    clearcoat_roughness: f64,
+    transmission: f64,
+    ior: f64,
+}
+
+/// Roughness is clamped to this so that every lobe stays glossy.
+const MIN_ROUGHNESS: f64 = 0.04;
+
+const CLEARCOAT_F0: f64 = 0.04;
+
+fn schlick(f0: DVec3, cos_theta: f64) -> DVec3 {
+    f0 + (DVec3::ONE - f0) * (1.0 - cos_theta).clamp(0.0, 1.0).powi(5)
+}
+
+/// Probabilities of sampling each lobe.
+struct LobeProbabilities {
+    diffuse: f64,
+    specular: f64,
+    clearcoat: f64,
+    transmission: f64,
+}
+
+impl Principled {
+    fn bsdf(&self, hr: &HitRecord) -> Bsdf {
+        Bsdf {
+            base_colour: self.base_colour.value(hr),
+            metallic: self.metallic.scalar(hr),
+            roughness: self.roughness.scalar(hr),
+            anisotropy: self.anisotropy,
+            specular: self.specular,
+            specular_tint: self.specular_tint,
+            sheen: self.sheen,
+            sheen_tint: self.sheen_tint,
+            clearcoat: self.clearcoat,
+            clearcoat_roughness: self.clearcoat_roughness,
+            transmission: self.transmission,
+            ior: self.ior,
+        }
+    }
+}
+
+impl Bsdf {
+    /// Base colour normalised to unit luminance.
+    fn tint(&self) -> DVec3 {
+        let luminance = vector::luminance(self.base_colour);
+        if luminance > 0.0 {
+            self.base_colour / luminance
+        } else {
+            DVec3::ONE
+        }
+    }
+
+    fn specular_f0(&self) -> DVec3 {
+        let tint = DVec3::ONE.lerp(self.tint(), self.specular_tint);
+        (0.08 * self.specular * tint).lerp(self.base_colour, self.metallic)
+    }
+
+    fn distribution(&self) -> TrowbridgeReitz {
+        TrowbridgeReitz::from_roughness(self.roughness.max(MIN_ROUGHNESS), self.anisotropy)
+    }
+
+    fn clearcoat_distribution(&self) -> TrowbridgeReitz {
+        TrowbridgeReitz::from_roughness(self.clearcoat_roughness.max(MIN_ROUGHNESS), 0.0)
+    }
+
+    fn glass(&self) -> Dielectric {
+        Dielectric::new()
+            .ir(self.ior)
+            .roughness(self.roughness.max(MIN_ROUGHNESS))
+    }
+
+    fn diffuse_weight(&self) -> f64 {
+        (1.0 - self.metallic) * (1.0 - self.transmission)
+    }
+
+    fn transmission_weight(&self) -> f64 {
+        (1.0 - self.metallic) * self.transmission
+    }
+
+    /// Specular reflection is replaced by the glass lobe where transmissive.
+    fn specular_weight(&self) -> f64 {
+        1.0 - self.transmission_weight()
+    }
+
+    /// Fraction of the light seen from `wo` that comes through the
+    /// clearcoat from the layers below it.
+    fn clearcoat_transmission(&self, wo: DVec3) -> f64 {
+        1.0 - 0.25 * self.clearcoat * schlick(DVec3::splat(CLEARCOAT_F0), wo.z).x
+    }
+
+    /// Fraction of the light seen from `wo` that comes through the specular
+    /// layer from the diffuse base.
+    fn specular_transmission(&self, wo: DVec3) -> DVec3 {
+        DVec3::ONE - self.specular_weight() * schlick(self.specular_f0(), wo.z)
+    }
+
+    fn lobe_probabilities(&self, wo: DVec3) -> Option<LobeProbabilities> {
+        let coat = self.clearcoat_transmission(wo);
+        let diffuse = coat
+            * self.diffuse_weight()
+            * vector::luminance(self.base_colour * self.specular_transmission(wo)).max(1e-3);
+        let specular =
+            coat * self.specular_weight() * vector::luminance(schlick(self.specular_f0(), wo.z));
+        let clearcoat = 0.25 * self.clearcoat * schlick(DVec3::splat(CLEARCOAT_F0), wo.z).x;
+        let transmission = coat * self.transmission_weight();
+        let total = diffuse + specular + clearcoat + transmission;
+        (total > 0.0).then(|| LobeProbabilities {
+            diffuse: diffuse / total,
+            specular: specular / total,
+            clearcoat: clearcoat / total,
+            transmission: transmission / total,
+        })
+    }
+
+    /// Burley diffuse with sheen, times the cosine. Retro-reflection from
+    /// rough surfaces is capped at the Lambertian value, so that the base
+    /// never reflects more light than reaches it.
+    fn eval_diffuse(&self, wi: DVec3, wo: DVec3) -> DVec3 {
+        let wh = (wi + wo).normalize();
+        let cos_theta_d = wi.dot(wh);
+        let fd90 = (0.5 + 2.0 * self.roughness * cos_theta_d * cos_theta_d).min(1.0);
+        let fresnel = |cos: f64| 1.0 + (fd90 - 1.0) * (1.0 - cos).powi(5);
+        let diffuse = self.base_colour / PI * fresnel(wi.z) * fresnel(wo.z);
+        let sheen_colour = DVec3::ONE.lerp(self.tint(), self.sheen_tint);
+        let sheen = self.sheen * sheen_colour * (1.0 - cos_theta_d).powi(5);
+        self.diffuse_weight() * (diffuse + sheen) * wi.z
+    }
+
+    /// Microfacet reflection, times the cosine.
+    fn eval_microfacet(distribution: TrowbridgeReitz, f0: DVec3, wi: DVec3, wo: DVec3) -> DVec3 {
+        let wm = (wi + wo).normalize();
+        let f = schlick(f0, wo.dot(wm));
+        f * distribution.d(wm) * distribution.g(wo, wi) / (4.0 * wo.z)
+    }
+
+    fn pdf_microfacet(distribution: TrowbridgeReitz, wi: DVec3, wo: DVec3) -> f64 {
+        let wm = (wi + wo).normalize();
+        distribution.d_visible(wo, wm) / (4.0 * wo.dot(wm).abs())
+    }
+
+    fn eval_reflection(&self, wi: DVec3, wo: DVec3) -> DVec3 {
+        if wi.z <= 0.0 || wo.z <= 0.0 {
+            return DVec3::ZERO;
+        }
+        let specular = Self::eval_microfacet(self.distribution(), self.specular_f0(), wi, wo);
+        let clearcoat = Self::eval_microfacet(
+            self.clearcoat_distribution(),
+            DVec3::splat(CLEARCOAT_F0),
+            wi,
+            wo,
+        );
+        // Light reflected by each layer doesn't reach the layers below
+        let base = self.specular_transmission(wo) * self.eval_diffuse(wi, wo)
+            + self.specular_weight() * specular;
+        self.clearcoat_transmission(wo) * base + 0.25 * self.clearcoat * clearcoat
+    }
+
+    fn eval_transmission(&self, hr: &HitRecord, wi: DVec3, wo: DVec3) -> DVec3 {
+        if self.transmission_weight() <= 0.0 {
+            return DVec3::ZERO;
+        }
+        // Only light passing through the surface takes on its colour
+        let tint = if wi.dot(hr.normal) < 0.0 {
+            self.base_colour
+        } else {
+            DVec3::ONE
+        };
+        self.transmission_weight() * tint * self.glass().eval(hr, wi, wo)
+    }
+}
+
+impl Bsdf {
+    fn sample(&self, r: &Ray, hr: &HitRecord) -> Option<BsdfSample> {
+        let onb = hr.shading_frame();
+        let wo = onb.to_local(-r.direction);
+        let p = self.lobe_probabilities(wo)?;
+        let u: f64 = random();
+        let wi = if u < p.diffuse {
+            onb.to_world(vector::random_cosine_direction())
+        } else if u < p.diffuse + p.specular + p.clearcoat {
+            let distribution = if u < p.diffuse + p.specular {
+                self.distribution()
+            } else {
+                self.clearcoat_distribution()
+            };
+            let wm = distribution.sample_wm(wo);
+            onb.to_world(vector::reflect(-wo, wm))
+        } else {
+            self.glass().sample(r, hr)?.wi
+        };
+
+        let wo = -r.direction;
+        let pdf = self.pdf(hr, wi, wo);
+        let lobe = if wi.dot(hr.normal) < 0.0 {
+            Lobe::GLOSSY | Lobe::TRANSMISSION
+        } else if u < p.diffuse {
+            Lobe::DIFFUSE | Lobe::REFLECTION
+        } else {
+            Lobe::GLOSSY | Lobe::REFLECTION
+        };
+        (pdf > 0.0).then(|| BsdfSample {
+            value: self.eval(hr, wi, wo),
+            wi,
+            pdf,
+            lobe,
+        })
+    }
+
+    fn eval(&self, hr: &HitRecord, wi: DVec3, wo: DVec3) -> DVec3 {
+        let onb = hr.shading_frame();
+        let wo_local = onb.to_local(wo);
+        self.eval_reflection(onb.to_local(wi), wo_local)
+            + self.clearcoat_transmission(wo_local) * self.eval_transmission(hr, wi, wo)
+    }
+
+    fn pdf(&self, hr: &HitRecord, wi: DVec3, wo: DVec3) -> f64 {
+        let onb = hr.shading_frame();
+        let (wi_local, wo_local) = (onb.to_local(wi), onb.to_local(wo));
+        let Some(p) = self.lobe_probabilities(wo_local) else {
+            return 0.0;
+        };
+        let mut pdf = 0.0;
+        if wi_local.z > 0.0 && wo_local.z > 0.0 {
+            pdf += p.diffuse * vector::cosine_hemisphere_pdf(wi_local.z)
+                + p.specular * Self::pdf_microfacet(self.distribution(), wi_local, wo_local)
+                + p.clearcoat
+                    * Self::pdf_microfacet(self.clearcoat_distribution(), wi_local, wo_local);
+        }
+        if p.transmission > 0.0 {
+            pdf += p.transmission * self.glass().pdf(hr, wi, wo);
+        }
+        pdf
+    }
+}
+
+impl Material for Principled {
+    fn sample(&self, r: &Ray, hr: &HitRecord) -> Option<BsdfSample> {
+        self.bsdf(hr).sample(r, hr)
+    }
+
+    fn eval(&self, hr: &HitRecord, wi: DVec3, wo: DVec3) -> DVec3 {
+        self.bsdf(hr).eval(hr, wi, wo)
+    }
+
+    fn pdf(&self, hr: &HitRecord, wi: DVec3, wo: DVec3) -> f64 {
+        self.bsdf(hr).pdf(hr, wi, wo)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sample_matches_eval() {
+        let materials = [
+            Principled::new(),
+            Principled::new()
+                .metallic(1.0)
+                .roughness(0.2)
+                .anisotropy(0.5),
+            Principled::new()
+                .sheen(1.0)
+                .clearcoat(1.0)
+                .specular_tint(0.5),
+            Principled::new().transmission(1.0).roughness(0.3),
+            Principled::new()
+                .transmission(0.5)
+                .metallic(0.2)
+                .roughness(0.0),
+        ];
+        let r = Ray::new(DVec3::new(0.0, 1.0, 0.0), DVec3::new(0.3, -1.0, 0.2));
+        for material in &materials {
+            let hr = HitRecord::new(&r, DVec3::ZERO, 1.0, DVec3::Y, material)
+                .with_derivatives(DVec3::new(1.0, 0.3, 0.5), DVec3::ZERO);
+            let wo = -r.direction;
+            for _ in 0..200 {
+                let Some(s) = material.sample(&r, &hr) else {
+                    continue;
+                };
+                assert!(s.pdf.is_finite() && s.value.is_finite());
+                assert!((s.pdf - material.pdf(&hr, s.wi, wo)).abs() <= 1e-9 * s.pdf);
+                assert_eq!(s.lobe.is_transmission(), s.wi.dot(hr.normal) < 0.0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_anisotropy() {
+        let material = Principled::new()
+            .metallic(1.0)
+            .roughness(0.3)
+            .anisotropy(1.0);
+        let r = Ray::new(DVec3::new(0.0, 1.0, 0.0), DVec3::NEG_Y);
+        let hr = HitRecord::new(&r, DVec3::ZERO, 1.0, DVec3::Y, &material);
+        let along_x = DVec3::new(0.3, 1.0, 0.0).normalize();
+        let along_z = DVec3::new(0.0, 1.0, 0.3).normalize();
+        for (dpdu, stretched, narrow) in
+            [(DVec3::X, along_x, along_z), (DVec3::Z, along_z, along_x)]
+        {
+            let hr = hr.with_derivatives(dpdu, DVec3::ZERO);
+            let f = |wi| material.eval(&hr, wi, DVec3::Y).x;
+            assert!(f(stretched) > 2.0 * f(narrow), "{dpdu}");
+        }
+    }
+
+    #[test]
+    fn test_energy_conservation() {
+        // Estimate the albedo of white, non-metallic surfaces
+        let white = Principled::new().base_colour(DVec3::ONE);
+        for material in [
+            white.clone().roughness(0.5),
+            white.clone().roughness(0.1).specular(1.0),
+            white.clone().roughness(1.0),
+            white.clone().roughness(0.5).sheen(1.0).clearcoat(1.0),
+        ] {
+            for direction in [DVec3::new(0.5, -1.0, 0.0), DVec3::new(3.0, -1.0, 0.0)] {
+                let r = Ray::new(DVec3::new(0.0, 1.0, 0.0), direction);
+                let hr = HitRecord::new(&r, DVec3::ZERO, 1.0, DVec3::Y, &material);
+                let n = 20_000;
+                let albedo: DVec3 = (0..n)
+                    .filter_map(|_| material.sample(&r, &hr))
+                    .map(|s| s.weight())
+                    .sum::<DVec3>()
+                    / n as f64;
+                assert!(albedo.max_element() <= 1.0, "albedo {albedo}");
+                assert!(albedo.min_element() > 0.8, "albedo {albedo}");
+            }
+        }
+    }
+}
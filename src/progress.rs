@@ -0,0 +1,113 @@
+use std::time::Duration;
+
+use indicatif::ProgressBar;
+
+use crate::stats::Counters;
+
+/// Size of the image and the most work the render will do.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct RenderInfo {
+    pub image_width: u32,
+    pub image_height: u32,
+    pub max_passes: usize,
+    pub tiles: usize,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct TileStats {
+    pub pass: usize,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub counters: Counters,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct PassStats {
+    pub pass: usize,
+    pub samples_per_pixel: usize,
+    pub elapsed: Duration,
+    /// Mean relative standard error of the pixel luminances.
+    pub noise: f64,
+    /// Totals since the start of the render.
+    pub counters: Counters,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct RenderStats {
+    pub passes: usize,
+    pub samples_per_pixel: usize,
+    pub elapsed: Duration,
+    pub noise: f64,
+    pub counters: Counters,
+}
+
+impl PassStats {
+    pub fn rays_per_second(&self) -> f64 {
+        self.counters.rays as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+impl RenderStats {
+    pub fn rays_per_second(&self) -> f64 {
+        self.counters.rays as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+/// Receives progress updates from `Camera::render_with_observer`. Tiles are
+/// reported from the worker threads as they complete.
+pub trait RenderObserver: Sync {
+    fn render_started(&self, _info: &RenderInfo) {}
+    fn tile_completed(&self, _tile: &TileStats) {}
+    fn pass_completed(&self, _pass: &PassStats) {}
+    fn render_completed(&self, _stats: &RenderStats) {}
+}
+
+/// Observer that ignores all updates.
+impl RenderObserver for () {}
+
+/// Progress bar on stderr, counting tiles across all passes.
+pub struct TerminalProgress {
+    bar: ProgressBar,
+}
+
+impl TerminalProgress {
+    pub fn new() -> Self {
+        Self {
+            bar: ProgressBar::new(0),
+        }
+    }
+}
+
+impl Default for TerminalProgress {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RenderObserver for TerminalProgress {
+    fn render_started(&self, info: &RenderInfo) {
+        self.bar.set_length((info.max_passes * info.tiles) as u64);
+    }
+
+    fn tile_completed(&self, _tile: &TileStats) {
+        self.bar.inc(1);
+    }
+
+    fn render_completed(&self, stats: &RenderStats) {
+        self.bar.finish_and_clear();
+        eprintln!(
+            "Completed {} samples per pixel in {:.3} seconds ({:.2} Mrays/s)",
+            stats.samples_per_pixel,
+            stats.elapsed.as_secs_f32(),
+            stats.rays_per_second() / 1e6,
+        );
+        if stats.counters.truncated_paths > 0 {
+            eprintln!(
+                "{} paths were cut short by the limit on scattering in media",
+                stats.counters.truncated_paths
+            );
+        }
+    }
+}
@@ -1,9 +1,19 @@
-use glam::DVec3;
+use glam::{DVec3, DVec4};
 
+use rand::random;
+
+use crate::spectrum::Wavelengths;
+
+#[derive(Clone, Copy, Debug)]
 pub struct Ray {
     pub origin: DVec3,
     pub direction: DVec3,
     pub inv_direction: DVec3,
+    /// Set for rays traced in spectral mode.
+    pub wavelengths: Option<Wavelengths>,
+    /// Random value fixed for the ray, so that random choices about the
+    /// same point along it come out the same however often it is tested.
+    pub seed: u64,
 }
 
 #[derive(Clone, Copy, PartialEq, Debug)]
@@ -19,9 +29,47 @@ impl Ray {
             origin,
             direction,
             inv_direction: 1.0 / direction,
+            wavelengths: None,
+            seed: random(),
         }
     }
 
+    pub fn with_wavelengths(self, wavelengths: Option<Wavelengths>) -> Self {
+        Self {
+            wavelengths,
+            ..self
+        }
+    }
+
+    /// A colour as carried along this ray: its spectrum at the ray's
+    /// wavelengths in spectral mode, and otherwise the RGB colour itself,
+    /// with an unused fourth component.
+    pub fn spectrum(&self, rgb: DVec3) -> DVec4 {
+        match &self.wavelengths {
+            Some(wavelengths) => wavelengths.spectrum(rgb),
+            None => rgb.extend(0.0),
+        }
+    }
+
+    /// Film colour of radiance carried along this ray, as from `spectrum`.
+    pub fn film_colour(&self, radiance: DVec4) -> DVec3 {
+        match &self.wavelengths {
+            Some(wavelengths) => wavelengths.film_colour(radiance),
+            None => radiance.truncate(),
+        }
+    }
+
+    /// Uniform random number in [0, 1), always the same for this ray and
+    /// `key`.
+    pub fn random(&self, key: u64) -> f64 {
+        // SplitMix64 finaliser
+        let mut z = self.seed ^ key.wrapping_mul(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        (z >> 11) as f64 / (1u64 << 53) as f64
+    }
+
     pub fn at(&self, t: f64) -> DVec3 {
         self.origin + t * self.direction
     }
@@ -0,0 +1,269 @@
+use std::{ptr, sync::OnceLock};
+
+use glam::{DVec3, DVec4};
+
+use crate::{
+    bvh::BVH,
+    hit::HitRecord,
+    light::{self, Gradient, Light},
+    light_tree::LightTree,
+    medium::Medium,
+    ray::{Interval, Ray},
+    spectrum::Wavelengths,
+};
+
+/// Everything a camera renders: the primitives in a `BVH`, the lights and
+/// the medium that fills the space between primitives. Until lights are
+/// added, the scene is lit by the `Gradient` sky of Ray Tracing in One
+/// Weekend.
+pub struct Scene {
+    bvh: BVH,
+    lights: Vec<Box<dyn Light>>,
+    default_lights: bool,
+    /// Built over the lights when first needed.
+    light_tree: OnceLock<LightTree>,
+    atmosphere: Option<Box<dyn Medium>>,
+}
+
+impl Scene {
+    pub fn new(bvh: BVH) -> Self {
+        Self {
+            bvh,
+            lights: vec![Box::new(Gradient::new())],
+            default_lights: true,
+            light_tree: OnceLock::new(),
+            atmosphere: None,
+        }
+    }
+
+    /// Add a light, replacing the default sky. Emissive primitives only
+    /// light the scene through scattered rays unless they are also added as
+    /// lights.
+    pub fn light(mut self, light: impl Light + 'static) -> Self {
+        if self.default_lights {
+            self.lights.clear();
+            self.default_lights = false;
+        }
+        self.lights.push(Box::new(light));
+        self.light_tree = OnceLock::new();
+        self
+    }
+
+    /// Fill the scene with fog or haze. Rays travel through it between
+    /// surfaces and on to the background, except inside objects with their
+    /// own interior. A `Homogeneous` medium fills all of space and so hides
+    /// the background, so outdoor scenes should use `Haze`, which ends at a
+    /// ceiling, or `HeightFog`, which thins out upwards.
+    pub fn atmosphere(self, medium: impl Medium + 'static) -> Self {
+        Self {
+            atmosphere: Some(Box::new(medium)),
+            ..self
+        }
+    }
+
+    pub fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord<'_>> {
+        self.bvh.hit(r, ray_t)
+    }
+
+    /// Medium outside all objects.
+    pub fn medium(&self) -> Option<&dyn Medium> {
+        self.atmosphere.as_deref()
+    }
+
+    fn light_tree(&self) -> &LightTree {
+        self.light_tree.get_or_init(|| LightTree::new(&self.lights))
+    }
+
+    /// Light whose primitive `r` hit at `hr`, if any. The primitive in the
+    /// scene is a copy of the light's own, which the ray hits at the same
+    /// distance with the same material.
+    fn hit_light(&self, r: &Ray, hr: &HitRecord) -> Option<usize> {
+        let candidates = self.light_tree().containing(hr.p);
+        candidates.into_iter().find(|&i| {
+            let hit = self.lights[i]
+                .emitter()
+                .and_then(|emitter| emitter.hit(r, Interval::new(1e-3, f64::INFINITY)));
+            hit.is_some_and(|light_hr| {
+                light_hr.t == hr.t && ptr::addr_eq(light_hr.material, hr.material)
+            })
+        })
+    }
+
+    /// Light reaching `p` from a light chosen by its likely contribution,
+    /// after scattering by `f`, which gives the BSDF or phase function for a
+    /// direction (times the cosine for surfaces), its sampling density and
+    /// the medium that the direction leads into. `n` is the surface normal
+    /// at `p`, or zero in media. Weighted for multiple importance sampling
+    /// with the density of scattering, and given at `wavelengths` in
+    /// spectral mode (see `Ray::spectrum`).
+    pub fn direct_light<'a>(
+        &'a self,
+        p: DVec3,
+        n: DVec3,
+        wavelengths: Option<Wavelengths>,
+        f: impl Fn(DVec3) -> (DVec3, f64, Option<&'a dyn Medium>),
+    ) -> DVec4 {
+        let Some((index, pmf)) = self.light_tree().sample(p, n) else {
+            return DVec4::ZERO;
+        };
+        let light = &self.lights[index];
+        let Some(sample) = light.sample(p) else {
+            return DVec4::ZERO;
+        };
+        if sample.pdf <= 0.0 || sample.radiance == DVec3::ZERO {
+            return DVec4::ZERO;
+        }
+        let (value, scatter_pdf, medium) = f(sample.wi);
+        if value == DVec3::ZERO {
+            return DVec4::ZERO;
+        }
+        let light_pdf = pmf * sample.pdf;
+        let shadow = Ray::new(p, sample.wi).with_wavelengths(wavelengths);
+        let transmittance = self.transmittance(&shadow, sample.distance, medium);
+        // Scattering can't find delta lights, so light sampling takes all
+        // the weight
+        let weight = if light.is_delta() {
+            1.0
+        } else {
+            light::power_heuristic(light_pdf, scatter_pdf)
+        };
+        shadow.spectrum(value)
+            * shadow.spectrum(sample.radiance)
+            * shadow.spectrum(transmittance)
+            * weight
+            / light_pdf
+    }
+
+    /// Radiance from the lights at infinity reaching a ray that leaves the
+    /// scene. `scatter_pdf` is the density with which the direction was
+    /// sampled, or `None` if lights couldn't have been sampled instead, as
+    /// for camera rays and specular scattering.
+    pub fn background(&self, r: &Ray, scatter_pdf: Option<f64>) -> DVec4 {
+        self.lights
+            .iter()
+            .map(|light| {
+                let radiance = light.background(r.direction);
+                r.spectrum(match scatter_pdf {
+                    Some(pdf) if radiance != DVec3::ZERO => {
+                        let light_pdf =
+                            self.light_tree().infinite_pmf() * light.pdf(r.origin, r.direction);
+                        radiance * light::power_heuristic(pdf, light_pdf)
+                    }
+                    _ => radiance,
+                })
+            })
+            .sum()
+    }
+
+    /// Light emitted by the surface at `hr` back along `r`, weighted like
+    /// `background`, with `origin` the point the ray was scattered from and
+    /// `n` the normal there (zero in media). The ray itself may start
+    /// further on, where it passed through the boundary of a medium. Only
+    /// the light that was hit could have been sampled instead, and surfaces
+    /// that aren't lights take all the weight.
+    pub fn emission(
+        &self,
+        r: &Ray,
+        hr: &HitRecord,
+        origin: DVec3,
+        n: DVec3,
+        scatter_pdf: Option<f64>,
+    ) -> DVec4 {
+        let emitted = hr.material.emitted(hr, -r.direction);
+        r.spectrum(match scatter_pdf {
+            Some(pdf) if emitted != DVec3::ZERO => {
+                let light_pdf = self.hit_light(r, hr).map_or(0.0, |i| {
+                    self.light_tree().pmf(origin, n, i) * self.lights[i].pdf(origin, r.direction)
+                });
+                emitted * light::power_heuristic(pdf, light_pdf)
+            }
+            _ => emitted,
+        })
+    }
+
+    /// Fraction of light travelling `distance` along a ray through media,
+    /// starting in `medium`, that arrives. Rays pass through the boundaries
+    /// of media but other surfaces block them.
+    pub fn transmittance<'a>(
+        &'a self,
+        r: &Ray,
+        distance: f64,
+        mut medium: Option<&'a dyn Medium>,
+    ) -> DVec3 {
+        let mut r = *r;
+        let mut distance = distance;
+        let mut transmittance = DVec3::ONE;
+        loop {
+            let hit = self.hit(&r, Interval::new(1e-3, distance * (1.0 - 1e-9)));
+            if let Some(medium) = medium {
+                transmittance *= medium.transmittance(&r, hit.map_or(distance, |hr| hr.t));
+            }
+            let Some(hr) = hit else {
+                return transmittance;
+            };
+            if !hr.material.is_interface() || transmittance == DVec3::ZERO {
+                return DVec3::ZERO;
+            }
+            medium = if hr.front_face {
+                hr.material.interior()
+            } else {
+                self.medium()
+            };
+            distance -= hr.t;
+            r = hr.ray(r.direction);
+        }
+    }
+}
+
+impl From<BVH> for Scene {
+    fn from(bvh: BVH) -> Self {
+        Self::new(bvh)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use crate::{hit::Hit, light::AreaLight, material::DiffuseLight, sphere::Sphere};
+
+    use super::*;
+
+    #[test]
+    fn test_emission_weight() {
+        // A lamp inside a larger one, which could also have been sampled in
+        // the direction of the hit
+        let lamp = |radius| {
+            let sphere = Sphere::new(
+                DVec3::new(0.0, 3.0, 0.0),
+                radius,
+                Arc::new(DiffuseLight::new()),
+            );
+            AreaLight::new(sphere, DiffuseLight::new())
+        };
+        let (small, large) = (lamp(0.5), lamp(1.0));
+        let scene = Scene::new(BVH::new([]))
+            .light(small.clone())
+            .light(large.clone());
+        let r = Ray::new(DVec3::ZERO, DVec3::Y);
+        let hr = small.hit(&r, Interval::new(0.0, f64::INFINITY)).unwrap();
+        let light_pdf = |i, light: &dyn Light| {
+            scene.light_tree().pmf(r.origin, DVec3::ZERO, i) * light.pdf(r.origin, r.direction)
+        };
+        assert!(light_pdf(1, &large) > 0.0);
+        // Only the small lamp could have been sampled
+        let expected = light::power_heuristic(0.1, light_pdf(0, &small));
+        let emission = scene.emission(&r, &hr, r.origin, DVec3::ZERO, Some(0.1));
+        assert!(
+            (emission.x - expected).abs() < 1e-12,
+            "{emission} {expected}"
+        );
+        // Emissive surfaces that aren't lights are only found by scattering
+        let other = lamp(0.5);
+        let hr = other.hit(&r, Interval::new(0.0, f64::INFINITY)).unwrap();
+        assert_eq!(
+            scene.emission(&r, &hr, r.origin, DVec3::ZERO, Some(0.1)).x,
+            1.0
+        );
+    }
+}
@@ -0,0 +1,276 @@
+use std::f64::consts::{FRAC_PI_2, PI};
+
+use glam::DVec3;
+use rand::random;
+
+use crate::{
+    light::{Light, LightSample},
+    spectrum,
+    util::default_struct,
+    vector::{self, Onb},
+};
+
+/// Radiance per kcd/m² of luminance, so that sunlit white surfaces come out
+/// around 1.
+const EXPOSURE: f64 = 0.04;
+
+/// Luminance of the sun's disc above the atmosphere, in kcd/m².
+const SUN_LUMINANCE: f64 = 1.6e6;
+
+/// Temperature of the sun's surface in kelvin.
+const SUN_TEMPERATURE: f64 = 5778.0;
+
+/// Direction towards a body at `elevation` above the horizon and `azimuth`
+/// clockwise from -Z seen from above, both in degrees.
+fn direction(elevation: f64, azimuth: f64) -> DVec3 {
+    let (elevation, azimuth) = (elevation.to_radians(), azimuth.to_radians());
+    DVec3::new(
+        azimuth.sin() * elevation.cos(),
+        elevation.sin(),
+        -azimuth.cos() * elevation.cos(),
+    )
+}
+
+/// Perez luminance distribution for a direction at `theta` to the zenith and
+/// `gamma` to the sun.
+fn perez([a, b, c, d, e]: [f64; 5], theta: f64, gamma: f64) -> f64 {
+    (1.0 + a * (b / theta.cos()).exp()) * (1.0 + c * (d * gamma).exp() + e * gamma.cos().powi(2))
+}
+
+default_struct!(
+    /// Clear daylight sky after Preetham et al. (1999), lit by the sun at
+    /// `elevation` and `azimuth` in degrees (see `Sky::sun`). `turbidity`
+    /// ranges from 2 for a very clear sky to 10 for hazy summer days.
+    /// Directions below the horizon see the sky at the horizon.
+    #[derive(Copy)]
+    Sky {
+        elevation: f64 = 45.0,
+        azimuth: f64 = 0.0,
+        turbidity: f64 = 3.0,
+        intensity: f64 = 1.0,
+    }
+);
+
+impl Sky {
+    /// The sun that lights this sky.
+    pub fn sun(&self) -> Sun {
+        Sun::new()
+            .elevation(self.elevation)
+            .azimuth(self.azimuth)
+            .turbidity(self.turbidity)
+            .intensity(self.intensity)
+    }
+
+    /// Zenith angle of the sun, kept above the horizon where the model is
+    /// valid.
+    fn theta_sun(&self) -> f64 {
+        FRAC_PI_2 - self.elevation.to_radians().max(0.0)
+    }
+
+    /// Luminance in kcd/m² and chromaticity of the sky at the zenith.
+    fn zenith(&self) -> DVec3 {
+        let (t, theta) = (self.turbidity, self.theta_sun());
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta);
+        let luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let chromaticity = |[t2, t1, t0]: [[f64; 4]; 3]| {
+            let poly = |[a, b, c, d]: [f64; 4]| ((a * theta + b) * theta + c) * theta + d;
+            t * t * poly(t2) + t * poly(t1) + poly(t0)
+        };
+        let x = chromaticity([
+            [0.00166, -0.00375, 0.00209, 0.0],
+            [-0.02903, 0.06377, -0.03202, 0.00394],
+            [0.11693, -0.21196, 0.06052, 0.25886],
+        ]);
+        let y = chromaticity([
+            [0.00275, -0.00610, 0.00317, 0.0],
+            [-0.04214, 0.08970, -0.04153, 0.00516],
+            [0.15346, -0.26756, 0.06670, 0.26688],
+        ]);
+        DVec3::new(luminance, x, y)
+    }
+
+    /// Perez coefficients for luminance and the two chromaticities.
+    fn coefficients(&self) -> [[f64; 5]; 3] {
+        let t = self.turbidity;
+        [
+            [
+                0.1787 * t - 1.4630,
+                -0.3554 * t + 0.4275,
+                -0.0227 * t + 5.3251,
+                0.1206 * t - 2.5771,
+                -0.0670 * t + 0.3703,
+            ],
+            [
+                -0.0193 * t - 0.2592,
+                -0.0665 * t + 0.0008,
+                -0.0004 * t + 0.2125,
+                -0.0641 * t - 0.8989,
+                -0.0033 * t + 0.0452,
+            ],
+            [
+                -0.0167 * t - 0.2608,
+                -0.0950 * t + 0.0092,
+                -0.0079 * t + 0.2102,
+                -0.0441 * t - 1.6537,
+                -0.0109 * t + 0.0529,
+            ],
+        ]
+    }
+}
+
+impl Light for Sky {
+    fn sample(&self, p: DVec3) -> Option<LightSample> {
+        let wi = vector::random_unit();
+        Some(LightSample {
+            wi,
+            radiance: self.background(wi),
+            pdf: self.pdf(p, wi),
+            distance: f64::INFINITY,
+        })
+    }
+
+    fn pdf(&self, _p: DVec3, _wi: DVec3) -> f64 {
+        1.0 / (4.0 * PI)
+    }
+
+    fn background(&self, direction: DVec3) -> DVec3 {
+        let direction = direction.normalize();
+        let theta = direction.y.clamp(1e-3, 1.0).acos();
+        let theta_sun = self.theta_sun();
+        let sun = self::direction(self.elevation.max(0.0), self.azimuth);
+        let gamma = direction.dot(sun).clamp(-1.0, 1.0).acos();
+        let zenith = self.zenith();
+        let [f_luminance, f_x, f_y] = self
+            .coefficients()
+            .map(|c| perez(c, theta, gamma) / perez(c, 0.0, theta_sun));
+        let (luminance, x, y) = (zenith.x * f_luminance, zenith.y * f_x, zenith.z * f_y);
+        let xyz = DVec3::new(x / y, 1.0, (1.0 - x - y) / y) * luminance;
+        (spectrum::xyz_to_linear_srgb(xyz) * EXPOSURE * self.intensity).max(DVec3::ZERO)
+    }
+}
+
+default_struct!(
+    /// Disc of the sun at `elevation` and `azimuth` in degrees, with
+    /// `angular_radius` in degrees, reddened by the atmosphere as it sets.
+    #[derive(Copy)]
+    Sun {
+        elevation: f64 = 45.0,
+        azimuth: f64 = 0.0,
+        turbidity: f64 = 3.0,
+        intensity: f64 = 1.0,
+        angular_radius: f64 = 0.267,
+    }
+);
+
+impl Sun {
+    fn direction(&self) -> DVec3 {
+        direction(self.elevation, self.azimuth)
+    }
+
+    fn cos_radius(&self) -> f64 {
+        self.angular_radius.to_radians().cos()
+    }
+
+    /// Radiance of the disc, attenuated by Rayleigh and aerosol scattering
+    /// along the path through the atmosphere.
+    pub fn radiance(&self) -> DVec3 {
+        if self.elevation <= 0.0 {
+            return DVec3::ZERO;
+        }
+        // Air mass relative to the zenith (Kasten and Young 1989)
+        let zenith_angle = 90.0 - self.elevation;
+        let air_mass = 1.0
+            / (zenith_angle.to_radians().cos() + 0.50572 * (96.07995 - zenith_angle).powf(-1.6364));
+        // Optical depths at the red, green and blue wavelengths in
+        // micrometres, with Angstrom's formula for aerosols
+        let lambda = DVec3::new(0.61, 0.55, 0.465);
+        let rayleigh = 0.008735 * lambda.powf(-4.08);
+        let beta = 0.04608 * self.turbidity - 0.04586;
+        let aerosol = beta * lambda.powf(-1.3);
+        let transmittance = (-(rayleigh + aerosol) * air_mass).exp();
+        let colour = spectrum::blackbody_rgb(SUN_TEMPERATURE);
+        let colour = colour / vector::luminance(colour);
+        colour * transmittance * SUN_LUMINANCE * EXPOSURE * self.intensity
+    }
+}
+
+impl Light for Sun {
+    fn sample(&self, p: DVec3) -> Option<LightSample> {
+        if self.elevation <= 0.0 {
+            return None;
+        }
+        let cos_theta = 1.0 - random::<f64>() * (1.0 - self.cos_radius());
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * random::<f64>();
+        let wi = Onb::new(self.direction()).to_world(DVec3::new(
+            sin_theta * phi.cos(),
+            sin_theta * phi.sin(),
+            cos_theta,
+        ));
+        Some(LightSample {
+            wi,
+            radiance: self.radiance(),
+            pdf: self.pdf(p, wi),
+            distance: f64::INFINITY,
+        })
+    }
+
+    fn pdf(&self, _p: DVec3, wi: DVec3) -> f64 {
+        let cos_radius = self.cos_radius();
+        if wi.normalize().dot(self.direction()) >= cos_radius {
+            1.0 / (2.0 * PI * (1.0 - cos_radius))
+        } else {
+            0.0
+        }
+    }
+
+    fn background(&self, direction: DVec3) -> DVec3 {
+        if direction.normalize().dot(self.direction()) >= self.cos_radius() {
+            self.radiance()
+        } else {
+            DVec3::ZERO
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sky_colour() {
+        let sky = Sky::new().elevation(60.0);
+        let zenith = sky.background(DVec3::Y);
+        assert!(zenith.z > zenith.x, "{zenith}");
+        // The sky brightens towards the sun and the horizon
+        let towards_sun = sky.background(direction(30.0, 0.0));
+        let away = sky.background(direction(30.0, 180.0));
+        assert!(vector::luminance(towards_sun) > vector::luminance(away));
+        // and is paler at the horizon
+        let horizon = sky.background(direction(1.0, 90.0));
+        assert!(horizon.z / horizon.x < zenith.z / zenith.x);
+    }
+
+    #[test]
+    fn test_sunset() {
+        let noon = Sun::new().elevation(80.0).radiance();
+        let sunset = Sun::new().elevation(3.0).radiance();
+        assert!(noon.z / noon.x > 0.8, "{noon}");
+        assert!(sunset.z / sunset.x < 0.5, "{sunset}");
+        assert_eq!(Sun::new().elevation(-5.0).radiance(), DVec3::ZERO);
+    }
+
+    #[test]
+    fn test_sun_sampling() {
+        let sun = Sun::new().elevation(30.0).angular_radius(2.0);
+        for _ in 0..100 {
+            let s = sun.sample(DVec3::ZERO).unwrap();
+            assert!(s.pdf > 0.0);
+            assert_eq!(s.radiance, sun.background(s.wi));
+        }
+        // The density integrates to one over the disc
+        let solid_angle = 2.0 * PI * (1.0 - 2f64.to_radians().cos());
+        assert!((sun.pdf(DVec3::ZERO, sun.direction()) * solid_angle - 1.0).abs() < 1e-9);
+        assert_eq!(sun.pdf(DVec3::ZERO, DVec3::Y), 0.0);
+    }
+}
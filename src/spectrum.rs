@@ -0,0 +1,272 @@
+use std::sync::OnceLock;
+
+use glam::{DMat3, DVec3, DVec4};
+use rand::random;
+
+/// Shortest wavelength sampled in spectral mode, in nanometres.
+pub const LAMBDA_MIN: f64 = 380.0;
+/// Longest wavelength sampled in spectral mode, in nanometres.
+pub const LAMBDA_MAX: f64 = 720.0;
+
+const N_WAVELENGTHS: usize = 4;
+
+/// Wavelengths carried by a camera ray in spectral mode. The first is the hero
+/// wavelength, which wavelength-dependent scattering follows; the others are
+/// spaced evenly through the visible range and share the path until it
+/// reaches a dispersive surface.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Wavelengths {
+    pub lambda: [f64; N_WAVELENGTHS],
+    pub pdf: [f64; N_WAVELENGTHS],
+}
+
+impl Wavelengths {
+    pub fn sample() -> Self {
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+        let hero = LAMBDA_MIN + random::<f64>() * range;
+        let lambda = std::array::from_fn(|i| {
+            let lambda = hero + i as f64 * range / N_WAVELENGTHS as f64;
+            if lambda > LAMBDA_MAX {
+                lambda - range
+            } else {
+                lambda
+            }
+        });
+        Self {
+            lambda,
+            pdf: [1.0 / range; N_WAVELENGTHS],
+        }
+    }
+
+    pub fn hero(&self) -> f64 {
+        self.lambda[0]
+    }
+
+    /// Drop all but the hero wavelength, after the path has taken a
+    /// direction that only the hero wavelength would.
+    pub fn terminate_secondary(&mut self) {
+        if self.is_terminated() {
+            return;
+        }
+        self.pdf[0] /= N_WAVELENGTHS as f64;
+        self.pdf[1..].fill(0.0);
+    }
+
+    pub fn is_terminated(&self) -> bool {
+        self.pdf[1] == 0.0
+    }
+
+    /// Values at these wavelengths of a smooth spectrum with the given RGB
+    /// colour, for reflectances and emission met along the path.
+    pub fn spectrum(&self, rgb: DVec3) -> DVec4 {
+        DVec4::from_array(self.lambda.map(|lambda| rgb_to_spectrum(rgb, lambda)))
+    }
+
+    /// Film colour of spectral radiance arriving along a path carrying
+    /// these wavelengths, given at each wavelength.
+    pub fn film_colour(&self, radiance: DVec4) -> DVec3 {
+        let total: DVec3 = self
+            .lambda
+            .iter()
+            .zip(self.pdf)
+            .zip(radiance.to_array())
+            .filter(|&((_, pdf), _)| pdf > 0.0)
+            .map(|((&lambda, pdf), radiance)| radiance * rgb_matching(lambda) / pdf)
+            .sum();
+        total / N_WAVELENGTHS as f64
+    }
+}
+
+/// Analytic fit of the CIE 1931 colour matching functions (Wyman et al. 2013).
+pub fn cie_xyz(lambda: f64) -> DVec3 {
+    let g = |mu: f64, sigma1: f64, sigma2: f64| {
+        let sigma = if lambda < mu { sigma1 } else { sigma2 };
+        (-0.5 * ((lambda - mu) / sigma).powi(2)).exp()
+    };
+    DVec3::new(
+        1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7) - 0.065 * g(501.1, 20.4, 26.2),
+        0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1),
+        1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8),
+    )
+}
+
+pub fn xyz_to_linear_srgb(xyz: DVec3) -> DVec3 {
+    let m = DMat3::from_cols(
+        DVec3::new(3.2404542, -0.9692660, 0.0556434),
+        DVec3::new(-1.5371385, 1.8760108, -0.2040259),
+        DVec3::new(-0.4985314, 0.0415560, 1.0572252),
+    );
+    m * xyz
+}
+
+/// Linear sRGB response to a wavelength, normalised so that a constant
+/// spectrum of 1 is white (1, 1, 1).
+pub fn rgb_matching(lambda: f64) -> DVec3 {
+    static NORMALISATION: OnceLock<DVec3> = OnceLock::new();
+    let normalisation = NORMALISATION.get_or_init(|| {
+        let steps = 1000;
+        let step = (LAMBDA_MAX - LAMBDA_MIN) / steps as f64;
+        let total: DVec3 = (0..steps)
+            .map(|i| xyz_to_linear_srgb(cie_xyz(LAMBDA_MIN + (i as f64 + 0.5) * step)))
+            .sum();
+        1.0 / (total * step)
+    });
+    xyz_to_linear_srgb(cie_xyz(lambda)) * *normalisation
+}
+
+/// Value at `lambda` of a smooth spectrum with the given RGB colour (Smits
+/// 1999).
+pub fn rgb_to_spectrum(rgb: DVec3, lambda: f64) -> f64 {
+    #[rustfmt::skip]
+    mod basis {
+        pub const WHITE: [f64; 10] = [1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000];
+        pub const CYAN: [f64; 10] = [0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000];
+        pub const MAGENTA: [f64; 10] = [1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959];
+        pub const YELLOW: [f64; 10] = [0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840];
+        pub const RED: [f64; 10] = [0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149];
+        pub const GREEN: [f64; 10] = [0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025];
+        pub const BLUE: [f64; 10] = [1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496];
+    }
+    use basis::*;
+
+    let bin = ((lambda - LAMBDA_MIN) / (LAMBDA_MAX - LAMBDA_MIN) * 10.0) as usize;
+    let bin = bin.min(9);
+    let DVec3 { x: r, y: g, z: b } = rgb;
+    // Remove the white component, then the largest secondary and primary
+    let (min, mid_basis, mid, max_basis, max) = if r <= g && r <= b {
+        if g <= b {
+            (r, CYAN, g - r, BLUE, b - g)
+        } else {
+            (r, CYAN, b - r, GREEN, g - b)
+        }
+    } else if g <= r && g <= b {
+        if r <= b {
+            (g, MAGENTA, r - g, BLUE, b - r)
+        } else {
+            (g, MAGENTA, b - g, RED, r - b)
+        }
+    } else if r <= g {
+        (b, YELLOW, r - b, GREEN, g - r)
+    } else {
+        (b, YELLOW, g - b, RED, r - g)
+    };
+    min * WHITE[bin] + mid * mid_basis[bin] + max * max_basis[bin]
+}
+
+/// Spectral radiance of a black body at `temperature` in kelvin (Planck's
+/// law), with the wavelength in nanometres.
+pub fn blackbody(lambda: f64, temperature: f64) -> f64 {
+    const C: f64 = 299_792_458.0;
+    const H: f64 = 6.626_070_15e-34;
+    const KB: f64 = 1.380_649e-23;
+    if temperature <= 0.0 {
+        return 0.0;
+    }
+    let l = lambda * 1e-9;
+    2.0 * H * C * C / (l.powi(5) * ((H * C / (l * KB * temperature)).exp() - 1.0))
+}
+
+/// Linear sRGB colour of a black body, normalised so that its spectrum peaks
+/// at 1 (by Wien's displacement law). This only picks a colour, such as for a
+/// light of a given colour temperature, as hotter bodies aren't brighter.
+pub fn blackbody_rgb(temperature: f64) -> DVec3 {
+    const WIEN: f64 = 2.897_771_955e6;
+    if temperature <= 0.0 {
+        return DVec3::ZERO;
+    }
+    blackbody_radiance_rgb(temperature) / (blackbody(WIEN / temperature, temperature) * 1e-9)
+}
+
+/// Linear sRGB radiance of a black body, in watts per steradian per square
+/// metre. Brightness rises steeply with temperature, as in fire.
+pub fn blackbody_radiance_rgb(temperature: f64) -> DVec3 {
+    let steps = 34;
+    let step = (LAMBDA_MAX - LAMBDA_MIN) / steps as f64;
+    (0..steps)
+        .map(|i| {
+            let lambda = LAMBDA_MIN + (i as f64 + 0.5) * step;
+            // Planck's law is per metre of wavelength, and the steps are in nanometres
+            blackbody(lambda, temperature) * 1e-9 * rgb_matching(lambda) * step
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn round_trip(rgb: DVec3) -> DVec3 {
+        let steps = 1000;
+        let step = (LAMBDA_MAX - LAMBDA_MIN) / steps as f64;
+        (0..steps)
+            .map(|i| {
+                let lambda = LAMBDA_MIN + (i as f64 + 0.5) * step;
+                rgb_to_spectrum(rgb, lambda) * rgb_matching(lambda) * step
+            })
+            .sum()
+    }
+
+    #[test]
+    fn test_white_round_trip() {
+        assert!(round_trip(DVec3::ONE).abs_diff_eq(DVec3::ONE, 1e-3));
+    }
+
+    #[test]
+    fn test_colour_round_trip() {
+        for rgb in [
+            DVec3::new(0.8, 0.2, 0.1),
+            DVec3::new(0.1, 0.6, 0.2),
+            DVec3::new(0.2, 0.3, 0.9),
+            DVec3::X,
+            DVec3::Y,
+            DVec3::Z,
+            DVec3::new(0.0, 1.0, 1.0),
+            DVec3::new(1.0, 0.0, 1.0),
+            DVec3::new(1.0, 1.0, 0.0),
+        ] {
+            let error = (round_trip(rgb) - rgb).abs().max_element();
+            assert!(error < 0.015, "{rgb} became {}", round_trip(rgb));
+        }
+    }
+
+    #[test]
+    fn test_terminate_secondary() {
+        let mut wavelengths = Wavelengths::sample();
+        assert!(wavelengths
+            .lambda
+            .iter()
+            .all(|l| (LAMBDA_MIN..=LAMBDA_MAX).contains(l)));
+        // Only the hero wavelength contributes after termination
+        let white = wavelengths.film_colour(wavelengths.spectrum(DVec3::ONE));
+        wavelengths.terminate_secondary();
+        let hero = wavelengths.film_colour(wavelengths.spectrum(DVec3::ONE));
+        let expected = rgb_to_spectrum(DVec3::ONE, wavelengths.hero())
+            * rgb_matching(wavelengths.hero())
+            * (LAMBDA_MAX - LAMBDA_MIN);
+        assert!(hero.abs_diff_eq(expected, 1e-9));
+        assert!(white.is_finite() && hero.is_finite());
+    }
+
+    #[test]
+    fn test_blackbody() {
+        // Flames are red, and the sun's surface is close to white
+        let flame = blackbody_rgb(1500.0);
+        assert!(flame.x > flame.y && flame.y > flame.z.max(0.0), "{flame}");
+        let sun = blackbody_rgb(5800.0);
+        assert!(sun.min_element() / sun.max_element() > 0.7, "{sun}");
+        assert!(blackbody_rgb(300.0).max_element() < 1e-9);
+        assert_eq!(blackbody_rgb(0.0), DVec3::ZERO);
+    }
+
+    #[test]
+    fn test_blackbody_radiance() {
+        // Doubling the temperature more than multiplies the visible light by
+        // 2^4, as the peak of the spectrum moves towards it
+        let cool = blackbody_radiance_rgb(1500.0);
+        let hot = blackbody_radiance_rgb(3000.0);
+        assert!(hot.y > 16.0 * cool.y, "{cool} {hot}");
+        // The colour is the same as the normalised one
+        let colour = blackbody_rgb(3000.0);
+        assert!((hot / hot.x).abs_diff_eq(colour / colour.x, 1e-12));
+    }
+}
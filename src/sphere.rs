@@ -1,14 +1,19 @@
-use std::sync::Arc;
+use std::{f64::consts::PI, sync::Arc};
 
 use crate::{
     aabb::AABB,
     hit::{Hit, HitRecord},
+    light::{self, Light, LightSample},
+    light_tree::LightBounds,
     material::Material,
     ray::{Interval, Ray},
+    vector::{self, Onb},
 };
 
-use glam::DVec3;
+use glam::{DVec2, DVec3};
+use rand::random;
 
+#[derive(Clone)]
 pub struct Sphere {
     center: DVec3,
     radius: f64,
@@ -23,6 +28,30 @@ impl Sphere {
             material,
         }
     }
+
+    /// Cosine of the half-angle of the cone the sphere fills seen from `p`,
+    /// or `None` from inside it.
+    fn cos_theta_max(&self, p: DVec3) -> Option<f64> {
+        let sin2_theta_max = self.radius * self.radius / p.distance_squared(self.center);
+        (sin2_theta_max < 1.0).then(|| (1.0 - sin2_theta_max).sqrt())
+    }
+}
+
+/// Longitude and latitude of a point on the unit sphere, with v increasing
+/// from the bottom (-Y) and u increasing from -X around through +Z.
+fn uv(p: DVec3) -> DVec2 {
+    let theta = (-p.y).clamp(-1.0, 1.0).acos();
+    let phi = (-p.z).atan2(p.x) + PI;
+    DVec2::new(phi / (2.0 * PI), theta / PI)
+}
+
+/// Partial derivatives of the position on a sphere with respect to `uv`.
+fn derivatives(uv: DVec2, radius: f64) -> (DVec3, DVec3) {
+    let (sin_phi, cos_phi) = (2.0 * PI * uv.x).sin_cos();
+    let (sin_theta, cos_theta) = (PI * uv.y).sin_cos();
+    let dpdu = 2.0 * PI * radius * DVec3::new(sin_theta * sin_phi, 0.0, sin_theta * cos_phi);
+    let dpdv = PI * radius * DVec3::new(-cos_theta * cos_phi, sin_theta, cos_theta * sin_phi);
+    (dpdu, dpdv)
 }
 
 impl Hit for Sphere {
@@ -44,7 +73,13 @@ impl Hit for Sphere {
             .find(|t| ray_t.surrounds(*t))?;
         let p = r.at(t);
         let outward_normal = (p - self.center) / self.radius;
-        Some(HitRecord::new(r, p, t, outward_normal, &*self.material))
+        let uv = uv(outward_normal);
+        let (dpdu, dpdv) = derivatives(uv, self.radius);
+        Some(
+            HitRecord::new(r, p, t, outward_normal, &*self.material)
+                .with_uv(uv)
+                .with_derivatives(dpdu, dpdv),
+        )
     }
 
     fn aabb(&self) -> AABB {
@@ -72,11 +107,74 @@ impl Hit for Sphere {
         }
         AABB::intersection([self.aabb(), aabb])
     }
+
+    fn area(&self) -> f64 {
+        4.0 * PI * self.radius * self.radius
+    }
+
+    /// Samples the cone of directions that the sphere fills seen from `p`.
+    fn sample_direction(&self, p: DVec3) -> Option<DVec3> {
+        let cos_theta_max = self.cos_theta_max(p)?;
+        let cos_theta = 1.0 - random::<f64>() * (1.0 - cos_theta_max);
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * random::<f64>();
+        Some(Onb::new((self.center - p).normalize()).to_world(DVec3::new(
+            sin_theta * phi.cos(),
+            sin_theta * phi.sin(),
+            cos_theta,
+        )))
+    }
+
+    fn direction_pdf(&self, p: DVec3, wi: DVec3) -> f64 {
+        match self.cos_theta_max(p) {
+            Some(cos_theta_max)
+                if wi.normalize().dot((self.center - p).normalize()) >= cos_theta_max =>
+            {
+                1.0 / (2.0 * PI * (1.0 - cos_theta_max))
+            }
+            _ => 0.0,
+        }
+    }
+}
+
+/// Spheres with emissive materials light the scene when added to its lights
+/// as well as its primitives.
+impl Light for Sphere {
+    fn sample(&self, p: DVec3) -> Option<LightSample> {
+        light::sample_surface(self, p)
+    }
+
+    fn pdf(&self, p: DVec3, wi: DVec3) -> f64 {
+        self.direction_pdf(p, wi)
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        // Radiance seen from above, assumed to be the same everywhere
+        let top = self.center + 2.0 * self.radius * DVec3::Y;
+        let hr = self.hit(
+            &Ray::new(top, DVec3::NEG_Y),
+            Interval::new(0.0, f64::INFINITY),
+        )?;
+        Some(LightBounds {
+            aabb: self.aabb(),
+            power: hr.material.exitance(&hr)
+                * self.area()
+                * vector::luminance(hr.material.emitted(&hr, DVec3::Y)),
+            axis: DVec3::Y,
+            cos_theta_o: -1.0,
+            cos_theta_e: 0.0,
+            two_sided: false,
+        })
+    }
+
+    fn emitter(&self) -> Option<&dyn Hit> {
+        Some(self)
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::material::Lambertian;
+    use crate::material::{DiffuseLight, Lambertian};
 
     use super::*;
 
@@ -115,4 +213,38 @@ mod test {
         // Box to the left of the sphere
         assert!(sphere.clipped_aabb(DVec3::X, -5.2, -5.1).is_empty());
     }
+
+    #[test]
+    fn test_uv() {
+        assert!(uv(DVec3::X).abs_diff_eq(DVec2::new(0.5, 0.5), 1e-12));
+        // Longitude is arbitrary at the poles
+        assert_eq!(uv(DVec3::Y).y, 1.0);
+        assert_eq!(uv(-DVec3::Y).y, 0.0);
+        assert!(uv(DVec3::Z).abs_diff_eq(DVec2::new(0.25, 0.5), 1e-12));
+        assert!(uv(-DVec3::Z).abs_diff_eq(DVec2::new(0.75, 0.5), 1e-12));
+    }
+
+    #[test]
+    fn test_derivatives() {
+        let n = DVec3::new(0.3, -0.5, 0.8).normalize();
+        let (dpdu, dpdv) = derivatives(uv(n), 2.0);
+        assert!(dpdu.dot(n).abs() < 1e-12 && dpdv.dot(n).abs() < 1e-12);
+        // Right-handed with the outward normal
+        assert!(dpdu.cross(dpdv).normalize().abs_diff_eq(n, 1e-12));
+        // Moving along dpdu changes only u
+        let delta = 1e-6;
+        let moved = uv((2.0 * n + delta * dpdu).normalize());
+        assert!((moved - uv(n)).abs_diff_eq(DVec2::new(delta, 0.0), 1e-9));
+    }
+
+    #[test]
+    fn test_light_power() {
+        // Total power of an emissive sphere, less for narrower spreads
+        for light in [DiffuseLight::new(), DiffuseLight::new().spread(30.0)] {
+            let exitance = light.exitance();
+            let sphere = Sphere::new(DVec3::ZERO, 2.0, Arc::new(light.intensity(3.0)));
+            let power = sphere.bounds().unwrap().power;
+            assert!((power - 3.0 * exitance * 16.0 * PI).abs() < 1e-9, "{power}");
+        }
+    }
 }
@@ -0,0 +1,62 @@
+use std::{
+    cell::Cell,
+    ops::{Add, AddAssign, Sub},
+};
+
+/// Counts of the work done while tracing rays.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct Counters {
+    pub rays: u64,
+    pub node_visits: u64,
+    pub primitive_tests: u64,
+    /// Paths cut short after `MAX_SCATTER_EVENTS` scattering events in
+    /// media, losing the light they would have gathered beyond.
+    pub truncated_paths: u64,
+}
+
+impl Add for Counters {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            rays: self.rays + other.rays,
+            node_visits: self.node_visits + other.node_visits,
+            primitive_tests: self.primitive_tests + other.primitive_tests,
+            truncated_paths: self.truncated_paths + other.truncated_paths,
+        }
+    }
+}
+
+impl AddAssign for Counters {
+    fn add_assign(&mut self, other: Self) {
+        *self = *self + other;
+    }
+}
+
+impl Sub for Counters {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        Self {
+            rays: self.rays - other.rays,
+            node_visits: self.node_visits - other.node_visits,
+            primitive_tests: self.primitive_tests - other.primitive_tests,
+            truncated_paths: self.truncated_paths - other.truncated_paths,
+        }
+    }
+}
+
+thread_local! {
+    static COUNTERS: Cell<Counters> = Cell::new(Counters::default());
+}
+
+/// Add to the calling thread's counters.
+pub fn record(counters: Counters) {
+    COUNTERS.with(|c| c.set(c.get() + counters));
+}
+
+/// The calling thread's counters. Work done between two snapshots on the same
+/// thread is their difference.
+pub fn snapshot() -> Counters {
+    COUNTERS.with(Cell::get)
+}
@@ -0,0 +1,89 @@
+use glam::DVec3;
+
+use crate::{
+    hit::HitRecord,
+    material::{BsdfSample, Dielectric, Material},
+    medium::{Homogeneous, Medium},
+    ray::Ray,
+    util::default_struct,
+};
+
+default_struct!(
+    /// Translucent material such as skin, wax, marble or milk. Light refracts
+    /// through a dielectric boundary and takes a random walk through the
+    /// `medium` inside, so objects must be closed. The coefficients of the
+    /// medium are per scene unit; scale measured ones with
+    /// `Homogeneous::scaled`.
+    #[derive(Copy)]
+    Subsurface {
+        ior: f64 = 1.4,
+        roughness: f64 = 0.0,
+        medium: Homogeneous = Homogeneous::new(),
+    }
+);
+
+impl Subsurface {
+    fn boundary(&self) -> Dielectric {
+        Dielectric::new().ir(self.ior).roughness(self.roughness)
+    }
+}
+
+impl Material for Subsurface {
+    fn sample(&self, r: &Ray, hr: &HitRecord) -> Option<BsdfSample> {
+        self.boundary().sample(r, hr)
+    }
+
+    fn eval(&self, hr: &HitRecord, wi: DVec3, wo: DVec3) -> DVec3 {
+        self.boundary().eval(hr, wi, wo)
+    }
+
+    fn pdf(&self, hr: &HitRecord, wi: DVec3, wo: DVec3) -> f64 {
+        self.boundary().pdf(hr, wi, wo)
+    }
+
+    fn interior(&self) -> Option<&dyn Medium> {
+        Some(&self.medium)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_boundary_and_interior() {
+        let medium = Homogeneous::skin();
+        let material = Subsurface::new().ior(1.4).medium(medium);
+        let r = Ray::new(DVec3::new(0.0, 1.0, 0.0), DVec3::NEG_Y);
+        let hr = HitRecord::new(&r, DVec3::ZERO, 1.0, DVec3::Y, &material);
+        // A smooth boundary reflects or refracts straight into the medium
+        let (mut reflected, mut transmitted) = (0, 0);
+        for _ in 0..1000 {
+            let s = material
+                .sample(&r, &hr)
+                .expect("the boundary always scatters");
+            assert!(s.lobe.is_specular());
+            if s.lobe.is_transmission() {
+                assert!(s.wi.abs_diff_eq(DVec3::NEG_Y, 1e-12));
+                transmitted += 1;
+            } else {
+                assert!(s.wi.abs_diff_eq(DVec3::Y, 1e-12));
+                reflected += 1;
+            }
+        }
+        assert!(
+            reflected > 0 && transmitted > 900,
+            "{reflected} {transmitted}"
+        );
+        assert!(!material.is_interface());
+
+        // Light inside travels through the medium
+        let interior = material.interior().expect("subsurface has an interior");
+        let r = Ray::new(DVec3::ZERO, DVec3::X);
+        assert_eq!(
+            interior.transmittance(&r, 2.0),
+            medium.transmittance(&r, 2.0)
+        );
+        assert!(interior.transmittance(&r, 2.0).max_element() < 1.0);
+    }
+}
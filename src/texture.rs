@@ -0,0 +1,371 @@
+use std::{fmt, path::Path, sync::Arc};
+
+use glam::{DVec2, DVec3};
+use image::{ColorType, ImageResult};
+use rand::{seq::SliceRandom, thread_rng, Rng};
+
+use crate::{hit::HitRecord, util::default_struct, vector};
+
+/// Spatially varying value of a material parameter, looked up by surface UV
+/// coordinates and position. Scalar parameters use the first channel.
+pub trait Texture: Send + Sync {
+    fn value(&self, uv: DVec2, p: DVec3) -> DVec3;
+}
+
+impl Texture for DVec3 {
+    fn value(&self, _uv: DVec2, _p: DVec3) -> DVec3 {
+        *self
+    }
+}
+
+impl Texture for f64 {
+    fn value(&self, _uv: DVec2, _p: DVec3) -> DVec3 {
+        DVec3::splat(*self)
+    }
+}
+
+/// Shared handle to a texture, as stored in materials. Constants convert into
+/// it directly.
+#[derive(Clone)]
+pub struct TextureRef(pub Arc<dyn Texture>);
+
+impl TextureRef {
+    pub fn new(texture: impl Texture + 'static) -> Self {
+        Self(Arc::new(texture))
+    }
+
+    pub fn value(&self, hr: &HitRecord) -> DVec3 {
+        self.0.value(hr.uv, hr.p)
+    }
+
+    pub fn scalar(&self, hr: &HitRecord) -> f64 {
+        self.value(hr).x
+    }
+}
+
+impl From<DVec3> for TextureRef {
+    fn from(colour: DVec3) -> Self {
+        Self::new(colour)
+    }
+}
+
+impl From<f64> for TextureRef {
+    fn from(value: f64) -> Self {
+        Self::new(value)
+    }
+}
+
+impl<T: Texture + 'static> From<Arc<T>> for TextureRef {
+    fn from(texture: Arc<T>) -> Self {
+        Self(texture)
+    }
+}
+
+/// Handles are equal when they share a texture.
+impl PartialEq for TextureRef {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl fmt::Debug for TextureRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("TextureRef(..)")
+    }
+}
+
+default_struct!(
+    /// Solid checkerboard of cubes with side `scale` in world space.
+    Checker {
+        scale: f64 = 1.0,
+        #[into]
+        even: TextureRef = DVec3::ONE.into(),
+        #[into]
+        odd: TextureRef = DVec3::ZERO.into(),
+    }
+);
+
+impl Texture for Checker {
+    fn value(&self, uv: DVec2, p: DVec3) -> DVec3 {
+        let cell = (p / self.scale).floor();
+        if (cell.x + cell.y + cell.z).rem_euclid(2.0) == 0.0 {
+            self.even.0.value(uv, p)
+        } else {
+            self.odd.0.value(uv, p)
+        }
+    }
+}
+
+/// How image textures are sampled outside [0, 1].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Wrap {
+    Repeat,
+    Clamp,
+    Mirror,
+}
+
+impl Wrap {
+    fn apply(self, i: i64, n: i64) -> i64 {
+        match self {
+            Self::Repeat => i.rem_euclid(n),
+            Self::Clamp => i.clamp(0, n - 1),
+            Self::Mirror => {
+                let i = i.rem_euclid(2 * n);
+                if i < n {
+                    i
+                } else {
+                    2 * n - 1 - i
+                }
+            }
+        }
+    }
+}
+
+/// Bilinearly filtered image in linear RGB, with the origin of UV space at
+/// the bottom left.
+#[derive(Clone, PartialEq, Debug)]
+pub struct ImageTexture {
+    width: u32,
+    height: u32,
+    pixels: Vec<DVec3>,
+    /// Wrapping across the left and right edges.
+    pub wrap_u: Wrap,
+    /// Wrapping across the top and bottom edges.
+    pub wrap_v: Wrap,
+}
+
+impl ImageTexture {
+    /// Image from rows of linear RGB pixels, starting at the top.
+    pub fn new(width: u32, height: u32, pixels: Vec<DVec3>) -> Self {
+        assert_eq!(pixels.len(), (width * height) as usize);
+        Self {
+            width,
+            height,
+            pixels,
+            wrap_u: Wrap::Repeat,
+            wrap_v: Wrap::Repeat,
+        }
+    }
+
+    /// Load a PNG, which is assumed to be sRGB encoded, or a Radiance HDR
+    /// image.
+    pub fn load(path: impl AsRef<Path>) -> ImageResult<Self> {
+        let image = image::open(path)?;
+        let linear = matches!(image.color(), ColorType::Rgb32F | ColorType::Rgba32F);
+        let image = image.into_rgb32f();
+        let pixels = image
+            .pixels()
+            .map(|p| {
+                let rgb = DVec3::new(p[0] as f64, p[1] as f64, p[2] as f64);
+                if linear {
+                    rgb
+                } else {
+                    vector::srgb_to_linear(rgb)
+                }
+            })
+            .collect();
+        Ok(Self::new(image.width(), image.height(), pixels))
+    }
+
+    /// Wrap the same way across all edges.
+    pub fn wrap(self, wrap: Wrap) -> Self {
+        self.wrap_uv(wrap, wrap)
+    }
+
+    pub fn wrap_uv(self, wrap_u: Wrap, wrap_v: Wrap) -> Self {
+        Self {
+            wrap_u,
+            wrap_v,
+            ..self
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Pixel in column `x` and row `y`, counting rows from the top.
+    pub fn pixel(&self, x: u32, y: u32) -> DVec3 {
+        self.pixels[(y * self.width + x) as usize]
+    }
+
+    fn texel(&self, x: i64, y: i64) -> DVec3 {
+        let x = self.wrap_u.apply(x, self.width as i64);
+        let y = self.wrap_v.apply(y, self.height as i64);
+        self.pixels[(y * self.width as i64 + x) as usize]
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, uv: DVec2, _p: DVec3) -> DVec3 {
+        // Texel centres are at half-integer coordinates
+        let x = uv.x * self.width as f64 - 0.5;
+        let y = (1.0 - uv.y) * self.height as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        let top = self.texel(x0, y0).lerp(self.texel(x0 + 1, y0), fx);
+        let bottom = self.texel(x0, y0 + 1).lerp(self.texel(x0 + 1, y0 + 1), fx);
+        top.lerp(bottom, fy)
+    }
+}
+
+const PERLIN_POINTS: usize = 256;
+
+/// Gradient noise with values in roughly [-1, 1] (Perlin 1985).
+#[derive(Clone, PartialEq, Debug)]
+pub struct Perlin {
+    gradients: [DVec3; PERLIN_POINTS],
+    permutations: [[usize; PERLIN_POINTS]; 3],
+}
+
+impl Perlin {
+    pub fn new() -> Self {
+        Self::from_rng(&mut thread_rng())
+    }
+
+    pub fn from_rng(rng: &mut impl Rng) -> Self {
+        let gradients = std::array::from_fn(|_| {
+            let v = DVec3::new(rng.gen(), rng.gen(), rng.gen()) * 2.0 - 1.0;
+            v.try_normalize().unwrap_or(DVec3::X)
+        });
+        let permutations = std::array::from_fn(|_| {
+            let mut p = std::array::from_fn(|i| i);
+            p.shuffle(rng);
+            p
+        });
+        Self {
+            gradients,
+            permutations,
+        }
+    }
+
+    pub fn noise(&self, p: DVec3) -> f64 {
+        let cell = p.floor();
+        let f = p - cell;
+        // Hermite smoothing of the interpolation weights
+        let w = f * f * (3.0 - 2.0 * f);
+        let mut total = 0.0;
+        for corner in 0..8 {
+            let offset = DVec3::new(
+                (corner & 1) as f64,
+                ((corner >> 1) & 1) as f64,
+                ((corner >> 2) & 1) as f64,
+            );
+            let index = |axis: usize, c: f64| (c as i64 + offset[axis] as i64) as usize & 255;
+            let gradient = self.gradients[self.permutations[0][index(0, cell.x)]
+                ^ self.permutations[1][index(1, cell.y)]
+                ^ self.permutations[2][index(2, cell.z)]];
+            let weight = offset * w + (1.0 - offset) * (1.0 - w);
+            let weight = weight.x * weight.y * weight.z;
+            total += weight * gradient.dot(f - offset);
+        }
+        total
+    }
+
+    /// Fractional Brownian motion: octaves of noise doubling in frequency and
+    /// scaled by `gain` each time.
+    pub fn fbm(&self, p: DVec3, octaves: usize, gain: f64) -> f64 {
+        let mut total = 0.0;
+        let mut amplitude = 1.0;
+        let mut p = p;
+        for _ in 0..octaves {
+            total += amplitude * self.noise(p);
+            amplitude *= gain;
+            p *= 2.0;
+        }
+        total
+    }
+}
+
+impl Default for Perlin {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+default_struct!(
+    /// Perlin fBm noise mapped from [-1, 1] to [0, `colour`].
+    Noise {
+        scale: f64 = 1.0,
+        octaves: usize = 1,
+        gain: f64 = 0.5,
+        colour: DVec3 = DVec3::ONE,
+        perlin: Perlin = Perlin::new(),
+    }
+);
+
+impl Texture for Noise {
+    fn value(&self, _uv: DVec2, p: DVec3) -> DVec3 {
+        let noise = self.perlin.fbm(p * self.scale, self.octaves, self.gain);
+        self.colour * (0.5 * (1.0 + noise)).clamp(0.0, 1.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    #[test]
+    fn test_checker() {
+        let checker = Checker::new().scale(2.0).even(0.25).odd(DVec3::X);
+        assert_eq!(
+            checker.value(DVec2::ZERO, DVec3::splat(0.5)),
+            DVec3::splat(0.25)
+        );
+        assert_eq!(
+            checker.value(DVec2::ZERO, DVec3::new(2.5, 0.5, 0.5)),
+            DVec3::X
+        );
+        assert_eq!(
+            checker.value(DVec2::ZERO, DVec3::new(-0.5, 0.5, 0.5)),
+            DVec3::X
+        );
+    }
+
+    #[test]
+    fn test_image_filtering() {
+        let image = ImageTexture::new(2, 1, vec![DVec3::ZERO, DVec3::ONE]);
+        // Texel centres, and halfway between them
+        assert_eq!(image.value(DVec2::new(0.25, 0.5), DVec3::ZERO), DVec3::ZERO);
+        assert_eq!(image.value(DVec2::new(0.75, 0.5), DVec3::ZERO), DVec3::ONE);
+        assert_eq!(
+            image.value(DVec2::new(0.5, 0.5), DVec3::ZERO),
+            DVec3::splat(0.5)
+        );
+        // Wrapping around the left edge
+        assert_eq!(
+            image.value(DVec2::new(0.0, 0.5), DVec3::ZERO),
+            DVec3::splat(0.5)
+        );
+        let image = image.wrap(Wrap::Clamp);
+        assert_eq!(image.value(DVec2::new(0.0, 0.5), DVec3::ZERO), DVec3::ZERO);
+        let image = image.wrap(Wrap::Mirror);
+        assert_eq!(image.value(DVec2::new(1.1, 0.5), DVec3::ZERO), DVec3::ONE);
+        // Each axis wraps separately
+        let image = ImageTexture::new(1, 2, vec![DVec3::ZERO, DVec3::ONE]);
+        let image = image.wrap_uv(Wrap::Repeat, Wrap::Clamp);
+        assert_eq!(image.value(DVec2::new(0.0, 1.0), DVec3::ZERO), DVec3::ZERO);
+        assert_eq!(image.value(DVec2::new(1.0, 0.0), DVec3::ZERO), DVec3::ONE);
+    }
+
+    #[test]
+    fn test_perlin() {
+        let perlin = Perlin::from_rng(&mut StdRng::seed_from_u64(1));
+        // Zero at lattice points, continuous between them
+        assert_eq!(perlin.noise(DVec3::new(3.0, -2.0, 7.0)), 0.0);
+        let p = DVec3::new(0.3, 1.7, -2.2);
+        let d = perlin.noise(p + DVec3::splat(1e-6)) - perlin.noise(p);
+        assert!(d.abs() < 1e-4);
+        for i in 0..1000 {
+            let noise = perlin.fbm(DVec3::splat(i as f64 * 0.173), 4, 0.5);
+            assert!(noise.abs() <= 2.0);
+        }
+    }
+}
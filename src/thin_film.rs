@@ -0,0 +1,233 @@
+use std::{
+    f64::consts::PI,
+    ops::{Add, Div, Mul, Sub},
+};
+
+use glam::DVec3;
+
+use crate::{
+    spectrum::{self, LAMBDA_MAX, LAMBDA_MIN},
+    util::default_struct,
+};
+
+/// Wavelengths in nanometres that RGB indices of refraction are given at,
+/// for red, green and blue.
+const RGB_WAVELENGTHS: [f64; 3] = [610.0, 550.0, 465.0];
+
+/// Wavelengths at which reflectance is integrated over the visible range
+/// outside spectral mode, enough to resolve the fringes of films a few
+/// micrometres thick.
+const RGB_STEPS: usize = 48;
+
+default_struct!(
+    /// Thin transparent film on a surface, such as soap, oil or an
+    /// anti-reflection coating. Light reflected from the top and bottom of
+    /// the film interferes, colouring reflections by thickness (in
+    /// nanometres) and viewing angle.
+    #[derive(Copy)]
+    ThinFilm {
+        thickness: f64 = 500.0,
+        ior: f64 = 1.33,
+    }
+);
+
+impl ThinFilm {
+    /// Reflectance of the film for light arriving at `cos_theta_i` from a
+    /// medium with index `outside` onto a substrate with complex index of
+    /// refraction `eta + ik`, given per RGB channel and interpolated between
+    /// them (see `channel_value`). In spectral mode the reflectance is
+    /// evaluated at the hero `wavelength`, and otherwise integrated against
+    /// the response of each RGB channel.
+    pub fn reflectance(
+        &self,
+        cos_theta_i: f64,
+        outside: f64,
+        eta: DVec3,
+        k: DVec3,
+        wavelength: Option<f64>,
+    ) -> DVec3 {
+        let at = |lambda| {
+            let n3 = Complex::new(channel_value(eta, lambda), channel_value(k, lambda));
+            self.airy(cos_theta_i, outside, n3, lambda)
+        };
+        if let Some(lambda) = wavelength {
+            return DVec3::splat(at(lambda));
+        }
+        let step = (LAMBDA_MAX - LAMBDA_MIN) / RGB_STEPS as f64;
+        let (total, white) = (0..RGB_STEPS)
+            .map(|i| {
+                let lambda = LAMBDA_MIN + (i as f64 + 0.5) * step;
+                let response = spectrum::rgb_matching(lambda);
+                (at(lambda) * response, response)
+            })
+            .fold((DVec3::ZERO, DVec3::ZERO), |(a, b), (c, d)| (a + c, b + d));
+        (total / white).clamp(DVec3::ZERO, DVec3::ONE)
+    }
+
+    /// Sum of the waves reflected inside the film (Airy), averaged over
+    /// polarisations.
+    fn airy(&self, cos_theta_i: f64, n1: f64, n3: Complex, wavelength: f64) -> f64 {
+        let n2 = self.ior;
+        let cos1 = Complex::real(cos_theta_i.clamp(0.0, 1.0));
+        let sin2_1 = 1.0 - cos_theta_i * cos_theta_i;
+        let (n1, n2) = (Complex::real(n1), Complex::real(n2));
+        let cos2 = (Complex::real(1.0) - Complex::real(sin2_1) * (n1 / n2) * (n1 / n2)).sqrt();
+        let cos3 = (Complex::real(1.0) - Complex::real(sin2_1) * (n1 / n3) * (n1 / n3)).sqrt();
+
+        let s = |a: Complex, ca: Complex, b: Complex, cb: Complex| {
+            (a * ca - b * cb) / (a * ca + b * cb)
+        };
+        let p = |a: Complex, ca: Complex, b: Complex, cb: Complex| {
+            (b * ca - a * cb) / (b * ca + a * cb)
+        };
+        // Phase difference between successive reflections
+        let delta = Complex::real(4.0 * PI * self.thickness / wavelength) * n2 * cos2;
+        let phase = (Complex::new(0.0, 1.0) * delta).exp();
+        let total = |r12: Complex, r23: Complex| {
+            let r = (r12 + r23 * phase) / (Complex::real(1.0) + r12 * r23 * phase);
+            r.norm_sqr()
+        };
+        let rs = total(s(n1, cos1, n2, cos2), s(n2, cos2, n3, cos3));
+        let rp = total(p(n1, cos1, n2, cos2), p(n2, cos2, n3, cos3));
+        ((rs + rp) / 2.0).clamp(0.0, 1.0)
+    }
+}
+
+/// Value at `lambda` of a quantity given for the red, green and blue
+/// channels, such as an index of refraction, interpolated linearly between
+/// `RGB_WAVELENGTHS` and constant beyond them.
+pub fn channel_value(rgb: DVec3, lambda: f64) -> f64 {
+    let [red, green, blue] = RGB_WAVELENGTHS;
+    let lerp = |a: f64, b: f64, t: f64| a + (b - a) * t.clamp(0.0, 1.0);
+    if lambda >= green {
+        lerp(rgb.y, rgb.x, (lambda - green) / (red - green))
+    } else {
+        lerp(rgb.y, rgb.z, (green - lambda) / (green - blue))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct Complex {
+    re: f64,
+    im: f64,
+}
+
+impl Complex {
+    fn new(re: f64, im: f64) -> Self {
+        Self { re, im }
+    }
+
+    fn real(re: f64) -> Self {
+        Self::new(re, 0.0)
+    }
+
+    fn norm_sqr(self) -> f64 {
+        self.re * self.re + self.im * self.im
+    }
+
+    /// Principal square root.
+    fn sqrt(self) -> Self {
+        let norm = self.norm_sqr().sqrt();
+        let re = ((norm + self.re) / 2.0).max(0.0).sqrt();
+        let im = ((norm - self.re) / 2.0).max(0.0).sqrt();
+        Self::new(re, if self.im < 0.0 { -im } else { im })
+    }
+
+    fn exp(self) -> Self {
+        let (sin, cos) = self.im.sin_cos();
+        Self::new(cos, sin) * Self::real(self.re.exp())
+    }
+}
+
+impl Add for Complex {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self::new(self.re + other.re, self.im + other.im)
+    }
+}
+
+impl Sub for Complex {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        Self::new(self.re - other.re, self.im - other.im)
+    }
+}
+
+impl Mul for Complex {
+    type Output = Self;
+
+    fn mul(self, other: Self) -> Self {
+        Self::new(
+            self.re * other.re - self.im * other.im,
+            self.re * other.im + self.im * other.re,
+        )
+    }
+}
+
+impl Div for Complex {
+    type Output = Self;
+
+    fn div(self, other: Self) -> Self {
+        let denom = other.norm_sqr();
+        Self::new(
+            (self.re * other.re + self.im * other.im) / denom,
+            (self.im * other.re - self.re * other.im) / denom,
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::microfacet;
+
+    use super::*;
+
+    #[test]
+    fn test_vanishing_film() {
+        // Without thickness the film has no effect on the substrate's Fresnel
+        let film = ThinFilm::new().thickness(0.0);
+        for cos in [1.0, 0.7, 0.2] {
+            let r = film.reflectance(cos, 1.0, DVec3::splat(1.5), DVec3::ZERO, None);
+            let expected = microfacet::fresnel_dielectric(cos, 1.5);
+            assert!(
+                r.abs_diff_eq(DVec3::splat(expected), 1e-9),
+                "{r} {expected}"
+            );
+
+            let (eta, k) = (DVec3::new(0.2, 0.9, 1.1), DVec3::new(3.9, 2.4, 2.1));
+            let expected = microfacet::fresnel_conductor(cos, eta, k);
+            for (i, lambda) in RGB_WAVELENGTHS.into_iter().enumerate() {
+                let r = film.reflectance(cos, 1.0, eta, k, Some(lambda));
+                assert!((r.x - expected[i]).abs() < 1e-9, "{r} {expected}");
+            }
+            let r = film.reflectance(cos, 1.0, eta, k, None);
+            assert!(r.abs_diff_eq(expected, 0.1), "{r} {expected}");
+        }
+    }
+
+    #[test]
+    fn test_thick_film() {
+        // Fringes from a thick film are too fine to colour the reflection
+        let film = ThinFilm::new().thickness(5000.0);
+        let r = film.reflectance(1.0, 1.0, DVec3::splat(1.5), DVec3::ZERO, None);
+        assert!(r.max_element() - r.min_element() < 0.01, "{r}");
+    }
+
+    #[test]
+    fn test_quarter_wave_coating() {
+        // A quarter-wave layer with index sqrt(n) cancels reflection from glass
+        let n: f64 = 1.5;
+        let wavelength = 550.0;
+        let film = ThinFilm::new()
+            .ior(n.sqrt())
+            .thickness(wavelength / (4.0 * n.sqrt()));
+        let r = film.reflectance(1.0, 1.0, DVec3::splat(n), DVec3::ZERO, Some(wavelength));
+        assert!(r.max_element() < 1e-9);
+        // Half-wave layers reflect like the bare substrate
+        let film = film.thickness(wavelength / (2.0 * n.sqrt()));
+        let r = film.reflectance(1.0, 1.0, DVec3::splat(n), DVec3::ZERO, Some(wavelength));
+        assert!((r.x - 0.04).abs() < 1e-9);
+    }
+}
@@ -1,6 +1,10 @@
+/// Declares a struct with public fields, a `new` constructor using the given
+/// defaults and a builder-style setter per field. Setters of fields marked
+/// `#[into]` accept anything convertible into the field type.
 macro_rules! default_struct {
-    ($name:ident {$($field:ident : $type:ty = $default:expr),* $(,)?}) => {
-        #[derive(Clone, Copy, PartialEq, Debug)]
+    ($(#[$attr:meta])* $name:ident {$($(#[$into:ident])? $field:ident : $type:ty = $default:expr),* $(,)?}) => {
+        $(#[$attr])*
+        #[derive(Clone, PartialEq, Debug)]
         pub struct $name {
             $(pub $field: $type,)*
         }
@@ -8,11 +12,25 @@ macro_rules! default_struct {
             pub fn new() -> Self {
                 Self { $($field: $default,)* }
             }
-            $(
-            pub fn $field(self, $field: $type) -> Self {
-                Self { $field, ..self }
+            $(default_struct!(@setter $field $type $(, $into)?);)*
+        }
+        impl Default for $name {
+            fn default() -> Self {
+                Self::new()
+            }
+        }
+    };
+    (@setter $field:ident $type:ty) => {
+        pub fn $field(self, $field: $type) -> Self {
+            Self { $field, ..self }
+        }
+    };
+    (@setter $field:ident $type:ty, into) => {
+        pub fn $field(self, $field: impl Into<$type>) -> Self {
+            Self {
+                $field: $field.into(),
+                ..self
             }
-            )*
         }
     };
 }
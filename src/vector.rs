@@ -1,6 +1,8 @@
+use std::f64::consts::PI;
+
 use ::rand::thread_rng;
 use glam::DVec3;
-use rand::{distributions::uniform::SampleRange, Rng};
+use rand::{distributions::uniform::SampleRange, random, Rng};
 
 pub fn random_range<R>(range: R) -> DVec3
 where
@@ -39,15 +41,86 @@ pub fn random_within_unit_disk() -> DVec3 {
     }
 }
 
-// pub fn random_on_hemisphere(normal: DVec3) -> DVec3 {
-//     let p = random_unit();
-//     if normal.dot(p) > 0.0 {
-//         // Is the same hemisphere as the normal
-//         p
-//     } else {
-//         -p
-//     }
-// }
+/// Random direction in the hemisphere around +Z with density proportional to
+/// its z component (the cosine with the pole).
+pub fn random_cosine_direction() -> DVec3 {
+    let r1: f64 = random();
+    let r2: f64 = random();
+    let phi = 2.0 * PI * r1;
+    let r = r2.sqrt();
+    DVec3::new(r * phi.cos(), r * phi.sin(), (1.0 - r2).sqrt())
+}
+
+/// Solid angle density of `random_cosine_direction`.
+pub fn cosine_hemisphere_pdf(cos_theta: f64) -> f64 {
+    cos_theta.max(0.0) / PI
+}
+
+/// Orthonormal basis with `w` along a given direction, used as a local
+/// shading frame.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Onb {
+    pub u: DVec3,
+    pub v: DVec3,
+    pub w: DVec3,
+}
+
+impl Onb {
+    pub fn new(w: DVec3) -> Self {
+        // Building an Orthonormal Basis, Revisited (Duff et al. 2017)
+        let sign = 1.0f64.copysign(w.z);
+        let a = -1.0 / (sign + w.z);
+        let b = w.x * w.y * a;
+        Self {
+            u: DVec3::new(1.0 + sign * w.x * w.x * a, sign * b, -sign * w.x),
+            v: DVec3::new(b, sign + w.y * w.y * a, -w.y),
+            w,
+        }
+    }
+
+    /// Basis with `w` along a given direction and `u` along `tangent` made
+    /// perpendicular to it, or an arbitrary `u` if there is no tangent or it
+    /// is parallel to `w`.
+    pub fn from_tangent(w: DVec3, tangent: DVec3) -> Self {
+        let u = tangent - w * w.dot(tangent);
+        if u.length_squared() <= 1e-12 * tangent.length_squared() {
+            return Self::new(w);
+        }
+        let u = u.normalize();
+        Self {
+            u,
+            v: w.cross(u),
+            w,
+        }
+    }
+
+    /// Convert from local coordinates, where +Z is `w`.
+    pub fn to_world(&self, a: DVec3) -> DVec3 {
+        a.x * self.u + a.y * self.v + a.z * self.w
+    }
+
+    /// Convert to local coordinates, where +Z is `w`.
+    pub fn to_local(&self, a: DVec3) -> DVec3 {
+        DVec3::new(a.dot(self.u), a.dot(self.v), a.dot(self.w))
+    }
+}
+
+/// Relative luminance of a linear sRGB colour.
+pub fn luminance(c: DVec3) -> f64 {
+    c.dot(DVec3::new(0.2126, 0.7152, 0.0722))
+}
+
+/// Decode an sRGB encoded colour with channels in [0, 1].
+pub fn srgb_to_linear(c: DVec3) -> DVec3 {
+    let channel = |c: f64| {
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    DVec3::new(channel(c.x), channel(c.y), channel(c.z))
+}
 
 pub fn near_zero(v: DVec3) -> bool {
     v.abs_diff_eq(DVec3::ZERO, 1e-6)
@@ -63,3 +136,31 @@ pub fn refract(v: DVec3, n: DVec3, etai_over_etat: f64) -> DVec3 {
     let r_out_parallel = -(1.0 - r_out_perp.length_squared()).abs().sqrt() * n;
     r_out_perp + r_out_parallel
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_onb() {
+        for _ in 0..100 {
+            let w = random_unit();
+            let onb = Onb::new(w);
+            assert!((onb.u.length() - 1.0).abs() < 1e-12);
+            assert!((onb.v.length() - 1.0).abs() < 1e-12);
+            assert!(onb.u.dot(onb.v).abs() < 1e-12);
+            assert!(onb.u.dot(w).abs() < 1e-12);
+            assert!(onb.u.cross(onb.v).abs_diff_eq(w, 1e-12));
+
+            let a = random_unit();
+            assert!(onb.to_world(onb.to_local(a)).abs_diff_eq(a, 1e-12));
+
+            let onb = Onb::from_tangent(w, a);
+            assert!(onb.u.dot(w).abs() < 1e-12);
+            assert!(onb.u.dot(a) >= 0.0);
+            assert!(onb.u.cross(onb.v).abs_diff_eq(w, 1e-12));
+        }
+        assert_eq!(Onb::from_tangent(DVec3::Z, DVec3::ZERO), Onb::new(DVec3::Z));
+        assert_eq!(Onb::from_tangent(DVec3::Z, DVec3::Z), Onb::new(DVec3::Z));
+    }
+}
@@ -0,0 +1,519 @@
+use std::{
+    fs, io,
+    ops::{Add, Mul},
+    path::Path,
+};
+
+use glam::DVec3;
+use rand::random;
+
+use crate::{
+    aabb::AABB,
+    hit::{Hit, HitRecord},
+    material::{BsdfSample, Lobe, Material},
+    medium::{HenyeyGreenstein, Homogeneous, Medium, MediumEvent},
+    ray::{Interval, Ray},
+    spectrum,
+};
+
+/// Invisible surface bounding a medium. Rays pass straight through, entering
+/// the medium at front faces and leaving it at back faces.
+struct Interface<M> {
+    medium: M,
+}
+
+impl<M: Medium> Material for Interface<M> {
+    fn sample(&self, r: &Ray, _hr: &HitRecord) -> Option<BsdfSample> {
+        Some(BsdfSample {
+            value: DVec3::ONE,
+            wi: r.direction,
+            pdf: 1.0,
+            lobe: Lobe::SPECULAR | Lobe::TRANSMISSION,
+        })
+    }
+
+    fn interior(&self) -> Option<&dyn Medium> {
+        Some(&self.medium)
+    }
+
+    fn is_interface(&self) -> bool {
+        true
+    }
+}
+
+/// Fog, smoke or mist of constant density filling a closed `boundary`.
+/// Scattering events keep `albedo` of the light, and on average happen
+/// `1 / density` apart.
+///
+/// Only the shape of the boundary is used. Whatever material it was made
+/// with is never seen by rays, as every hit on it takes the invisible
+/// interface of the medium instead.
+pub struct ConstantMedium {
+    boundary: Box<dyn Hit>,
+    interface: Interface<Homogeneous>,
+}
+
+impl ConstantMedium {
+    pub fn new(boundary: Box<dyn Hit>, density: f64, albedo: DVec3) -> Self {
+        let medium = Homogeneous::from_albedo(albedo, DVec3::splat(1.0 / density));
+        Self {
+            boundary,
+            interface: Interface { medium },
+        }
+    }
+
+    /// Henyey-Greenstein asymmetry of the phase function, from -1 (back
+    /// scattering) through 0 (isotropic) to 1 (forward scattering).
+    pub fn g(self, g: f64) -> Self {
+        let medium = self.interface.medium.g(g);
+        Self {
+            interface: Interface { medium },
+            ..self
+        }
+    }
+}
+
+impl Hit for ConstantMedium {
+    fn aabb(&self) -> AABB {
+        self.boundary.aabb()
+    }
+
+    fn clipped_aabb(&self, axis: DVec3, t1: f64, t2: f64) -> AABB {
+        self.boundary.clipped_aabb(axis, t1, t2)
+    }
+
+    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord<'_>> {
+        let hr = self.boundary.hit(r, ray_t)?;
+        Some(HitRecord {
+            material: &self.interface,
+            ..hr
+        })
+    }
+}
+
+/// Dense 3D grid of voxel values, stored with X varying fastest and then Y.
+/// Values are at the centres of the voxels and interpolated between them.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Grid<T = f64> {
+    size: [usize; 3],
+    values: Vec<T>,
+}
+
+impl<T: Copy + Add<Output = T> + Mul<f64, Output = T>> Grid<T> {
+    pub fn new(size: [usize; 3], values: Vec<T>) -> Self {
+        assert_eq!(size.iter().product::<usize>(), values.len());
+        assert!(!values.is_empty());
+        Self { size, values }
+    }
+
+    pub fn map<U>(&self, f: impl Fn(T) -> U) -> Grid<U> {
+        Grid {
+            size: self.size,
+            values: self.values.iter().map(|&v| f(v)).collect(),
+        }
+    }
+
+    fn voxel(&self, x: usize, y: usize, z: usize) -> T {
+        self.values[(z * self.size[1] + y) * self.size[0] + x]
+    }
+
+    /// Trilinear interpolation at `p` in the unit cube spanned by the grid.
+    /// Points outside take the value of the nearest voxel.
+    pub fn lookup(&self, p: DVec3) -> T {
+        let mut index = [0; 3];
+        let mut next = [0; 3];
+        let mut frac = [0.0; 3];
+        for axis in 0..3 {
+            let n = self.size[axis];
+            let x = (p[axis] * n as f64 - 0.5).clamp(0.0, (n - 1) as f64);
+            index[axis] = x as usize;
+            next[axis] = (index[axis] + 1).min(n - 1);
+            frac[axis] = x - index[axis] as f64;
+        }
+        let [x0, y0, z0] = index;
+        let [x1, y1, z1] = next;
+        let [fx, fy, fz] = frac;
+        let lerp = |a: T, b: T, t: f64| a * (1.0 - t) + b * t;
+        let plane = |z| {
+            lerp(
+                lerp(self.voxel(x0, y0, z), self.voxel(x1, y0, z), fx),
+                lerp(self.voxel(x0, y1, z), self.voxel(x1, y1, z), fx),
+                fy,
+            )
+        };
+        lerp(plane(z0), plane(z1), fz)
+    }
+}
+
+impl Grid {
+    /// Load a grid from a text file holding its size along X, Y and Z
+    /// followed by the values, all separated by whitespace.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        let invalid = |e| io::Error::new(io::ErrorKind::InvalidData, e);
+        let mut tokens = text.split_whitespace();
+        let mut size = [0; 3];
+        for n in &mut size {
+            let token = tokens.next().ok_or_else(|| invalid("missing grid size"))?;
+            *n = token.parse().map_err(|_| invalid("invalid grid size"))?;
+        }
+        let values = tokens
+            .map(|token| token.parse().map_err(|_| invalid("invalid voxel value")))
+            .collect::<io::Result<Vec<f64>>>()?;
+        if values.is_empty() || values.len() != size.iter().product() {
+            return Err(invalid("voxel count doesn't match the grid size"));
+        }
+        Ok(Self::new(size, values))
+    }
+
+    /// Load a grid of the given size from raw little-endian 32-bit floats.
+    pub fn load_raw(path: impl AsRef<Path>, size: [usize; 3]) -> io::Result<Self> {
+        let bytes = fs::read(path)?;
+        if size.iter().product::<usize>() * 4 != bytes.len() || bytes.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "file size doesn't match the grid size",
+            ));
+        }
+        let values = bytes
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64)
+            .collect();
+        Ok(Self::new(size, values))
+    }
+
+    pub fn max(&self) -> f64 {
+        self.values.iter().copied().fold(0.0, f64::max)
+    }
+}
+
+/// Medium with density varying through a box, such as a cloud or smoke
+/// plume. Extinction is `sigma_t` per unit density and the same for every
+/// channel, while scattering events keep `albedo` of the light. Distances
+/// are sampled by delta tracking and transmittance is estimated by ratio
+/// tracking, against the maximum density as a majorant.
+pub struct GridMedium {
+    density: Grid,
+    bounds: AABB,
+    sigma_t: f64,
+    albedo: DVec3,
+    g: f64,
+    /// Radiance emitted per unit distance.
+    emission: Option<Grid<DVec3>>,
+    majorant: f64,
+}
+
+impl GridMedium {
+    pub fn new(density: Grid, bounds: AABB) -> Self {
+        Self {
+            majorant: density.max(),
+            density,
+            bounds,
+            sigma_t: 1.0,
+            albedo: DVec3::ONE,
+            g: 0.0,
+            emission: None,
+        }
+    }
+
+    pub fn sigma_t(self, sigma_t: f64) -> Self {
+        Self {
+            sigma_t,
+            majorant: sigma_t * self.density.max(),
+            ..self
+        }
+    }
+
+    pub fn albedo(self, albedo: DVec3) -> Self {
+        Self { albedo, ..self }
+    }
+
+    pub fn g(self, g: f64) -> Self {
+        Self { g, ..self }
+    }
+
+    /// Emit `colour` per unit distance, scaled by the values of `grid`.
+    pub fn emission(self, grid: &Grid, colour: DVec3) -> Self {
+        Self {
+            emission: Some(grid.map(|v| v * colour)),
+            ..self
+        }
+    }
+
+    /// Emit black body radiation for temperatures in kelvin, as from fire.
+    /// The radiance of each voxel is absolute, so hotter parts are brighter as
+    /// well as whiter, and is multiplied by `exposure` per unit distance to
+    /// bring it to the brightness of the rest of the scene. Replaces any
+    /// other emission.
+    pub fn temperature(self, grid: &Grid, exposure: f64) -> Self {
+        Self {
+            emission: Some(grid.map(|t| exposure * spectrum::blackbody_radiance_rgb(t))),
+            ..self
+        }
+    }
+
+    /// Part of the ray up to `t_max` inside the bounds.
+    fn segment(&self, r: &Ray, t_max: f64) -> Option<(f64, f64)> {
+        let t1 = (self.bounds.min - r.origin) * r.inv_direction;
+        let t2 = (self.bounds.max - r.origin) * r.inv_direction;
+        let t_min = t1.min(t2).max_element().max(0.0);
+        let t_max = t1.max(t2).min_element().min(t_max);
+        (t_min < t_max && self.majorant > 0.0).then_some((t_min, t_max))
+    }
+
+    /// Position of a point in the unit cube spanned by the grid.
+    fn local(&self, p: DVec3) -> DVec3 {
+        (p - self.bounds.min) / self.bounds.size()
+    }
+
+    /// Steps through tentative collisions along the ray, sampled with the
+    /// majorant, until `f` returns a value or the segment ends.
+    fn track<T>(
+        &self,
+        r: &Ray,
+        (mut t, t_max): (f64, f64),
+        mut f: impl FnMut(f64, DVec3) -> Option<T>,
+    ) -> Option<T> {
+        loop {
+            t -= (1.0 - random::<f64>()).ln() / self.majorant;
+            if t >= t_max {
+                return None;
+            }
+            if let Some(result) = f(t, self.local(r.at(t))) {
+                return Some(result);
+            }
+        }
+    }
+}
+
+impl Medium for GridMedium {
+    fn sample(&self, r: &Ray, t_max: f64) -> MediumEvent {
+        let mut emission = DVec3::ZERO;
+        let scatter = self.segment(r, t_max).and_then(|segment| {
+            self.track(r, segment, |t, p| {
+                let density = self.density.lookup(p);
+                if let Some(grid) = &self.emission {
+                    emission += grid.lookup(p) / self.majorant;
+                }
+                (random::<f64>() * self.majorant < self.sigma_t * density).then_some(t)
+            })
+        });
+        match scatter {
+            Some(t) => MediumEvent::Scatter {
+                t,
+                weight: self.albedo,
+                emission,
+                phase: HenyeyGreenstein::new(self.g),
+            },
+            None => MediumEvent::Pass {
+                weight: DVec3::ONE,
+                emission,
+            },
+        }
+    }
+
+    fn transmittance(&self, r: &Ray, t_max: f64) -> DVec3 {
+        let mut transmittance = 1.0;
+        if let Some(segment) = self.segment(r, t_max) {
+            self.track(r, segment, |_, p| {
+                transmittance *= 1.0 - self.sigma_t * self.density.lookup(p) / self.majorant;
+                None::<()>
+            });
+        }
+        DVec3::splat(transmittance)
+    }
+}
+
+/// Heterogeneous volume, such as a cloud, explosion or fire, filling the
+/// bounds of a `GridMedium`.
+pub struct GridVolume {
+    interface: Interface<GridMedium>,
+}
+
+impl GridVolume {
+    pub fn new(medium: GridMedium) -> Self {
+        Self {
+            interface: Interface { medium },
+        }
+    }
+}
+
+impl Hit for GridVolume {
+    fn aabb(&self) -> AABB {
+        self.interface.medium.bounds
+    }
+
+    fn clipped_aabb(&self, axis: DVec3, t1: f64, t2: f64) -> AABB {
+        let mask = axis.cmpgt(DVec3::ZERO);
+        let slab = AABB {
+            min: DVec3::select(mask, DVec3::splat(t1), DVec3::NEG_INFINITY),
+            max: DVec3::select(mask, DVec3::splat(t2), DVec3::INFINITY),
+        };
+        AABB::intersection([self.aabb(), slab])
+    }
+
+    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord<'_>> {
+        let bounds = self.aabb();
+        let t1 = (bounds.min - r.origin) * r.inv_direction;
+        let t2 = (bounds.max - r.origin) * r.inv_direction;
+        let (near, far) = (t1.min(t2), t1.max(t2));
+        let (t_near, t_far) = (near.max_element(), far.min_element());
+        if t_far < t_near {
+            return None;
+        }
+        // Faces are entered against the direction of the ray on one axis
+        let face = |t: DVec3, t_face: f64| {
+            let axis = DVec3::select(t.cmpeq(DVec3::splat(t_face)), DVec3::ONE, DVec3::ZERO);
+            axis * r.direction.signum()
+        };
+        let (t, outward_normal) = if ray_t.surrounds(t_near) {
+            (t_near, -face(near, t_near))
+        } else if ray_t.surrounds(t_far) {
+            (t_far, face(far, t_far))
+        } else {
+            return None;
+        };
+        let outward_normal = outward_normal.normalize();
+        Some(HitRecord::new(
+            r,
+            r.at(t),
+            t,
+            outward_normal,
+            &self.interface,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        cutout::Cutout,
+        layered::Mix,
+        material::Lambertian,
+        normal_map::{BumpMap, NormalMap},
+        sphere::Sphere,
+        subsurface::Subsurface,
+    };
+
+    fn unit_cube() -> AABB {
+        AABB::bounding_box([DVec3::ZERO, DVec3::ONE])
+    }
+
+    #[test]
+    fn test_grid_lookup() {
+        let grid = Grid::new([2, 1, 1], vec![1.0, 3.0]);
+        // Voxel centres and the edges beyond them
+        assert_eq!(grid.lookup(DVec3::new(0.25, 0.5, 0.5)), 1.0);
+        assert_eq!(grid.lookup(DVec3::new(0.5, 0.5, 0.5)), 2.0);
+        assert_eq!(grid.lookup(DVec3::new(0.75, 0.1, 0.9)), 3.0);
+        assert_eq!(grid.lookup(DVec3::new(1.0, 0.5, 0.5)), 3.0);
+    }
+
+    #[test]
+    fn test_grid_load() {
+        let path = std::env::temp_dir().join("raytracer_test_grid.txt");
+        fs::write(&path, "2 1 2\n0 1\n2 3\n").unwrap();
+        let grid = Grid::load(&path).unwrap();
+        assert_eq!(grid, Grid::new([2, 1, 2], vec![0.0, 1.0, 2.0, 3.0]));
+        fs::write(&path, "2 2 2\n0 1\n").unwrap();
+        assert!(Grid::load(&path).is_err());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_grid_transmittance() {
+        // Density rising linearly along X from 1 to 2
+        let grid = Grid::new([2, 1, 1], vec![0.5, 2.5]);
+        let medium = GridMedium::new(grid, unit_cube())
+            .sigma_t(2.0)
+            .albedo(DVec3::ZERO);
+        let r = Ray::new(DVec3::new(-1.0, 0.5, 0.5), DVec3::X);
+        let expected = (-2.0 * 1.5f64).exp();
+        let n = 20_000;
+        let passed = (0..n)
+            .filter(|_| matches!(medium.sample(&r, 3.0), MediumEvent::Pass { .. }))
+            .count();
+        assert!((passed as f64 / n as f64 - expected).abs() < 0.01);
+        let ratio: f64 = (0..n).map(|_| medium.transmittance(&r, 3.0).x).sum();
+        assert!((ratio / n as f64 - expected).abs() < 0.01);
+        // The ray ends before reaching the volume
+        assert_eq!(medium.transmittance(&r, 0.5), DVec3::ONE);
+    }
+
+    #[test]
+    fn test_constant_medium_boundary() {
+        // The material of the boundary is replaced by the medium's interface
+        let sphere = Sphere::new(DVec3::ZERO, 1.0, Arc::new(Lambertian::new()));
+        let smoke = ConstantMedium::new(Box::new(sphere), 0.5, DVec3::ONE);
+        let r = Ray::new(DVec3::new(-2.0, 0.0, 0.0), DVec3::X);
+        let hr = smoke.hit(&r, Interval::new(1e-3, f64::INFINITY)).unwrap();
+        assert!(hr.material.is_interface());
+        let s = hr.material.sample(&r, &hr).unwrap();
+        assert_eq!((s.wi, s.value), (r.direction, DVec3::ONE));
+        let interior = hr.material.interior().unwrap();
+        let expected = (-0.5f64).exp();
+        assert!((interior.transmittance(&r, 1.0).x - expected).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_mix_interface() {
+        let interface = |density: f64| {
+            let medium = Homogeneous::from_albedo(DVec3::ONE, DVec3::splat(1.0 / density));
+            Arc::new(Interface { medium }) as Arc<dyn Material>
+        };
+        let r = Ray::new(DVec3::new(-2.0, 0.0, 0.0), DVec3::X);
+        let density =
+            |material: &dyn Material| -material.interior().unwrap().transmittance(&r, 1.0).x.ln();
+        let both = Mix::new(interface(1.0), interface(2.0), 0.5);
+        assert!(both.is_interface());
+        assert!((density(&both) - 1.0).abs() < 1e-12);
+        // Half the rays scatter off the other side, whichever order they're in
+        let solid: Arc<dyn Material> = Arc::new(Lambertian::new());
+        for mix in [
+            Mix::new(solid.clone(), interface(2.0), 0.5),
+            Mix::new(interface(2.0), solid.clone(), 0.5),
+        ] {
+            assert!(!mix.is_interface());
+            assert!((density(&mix) - 2.0).abs() < 1e-12);
+        }
+        assert!(Mix::new(solid.clone(), solid, 0.5).interior().is_none());
+        // The interface's medium wins over one behind a scattering boundary
+        let skin = Subsurface::new().medium(Homogeneous::skin());
+        let mix = Mix::new(Arc::new(skin), interface(2.0), 0.5);
+        assert!((density(&mix) - 2.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_wrapped_interface() {
+        let medium = Homogeneous::from_albedo(DVec3::ONE, DVec3::ONE);
+        let interface: Arc<dyn Material> = Arc::new(Interface { medium });
+        let wrapped: [Box<dyn Material>; 3] = [
+            Box::new(NormalMap::new(interface.clone(), DVec3::new(0.5, 0.5, 1.0))),
+            Box::new(BumpMap::new(interface.clone(), 0.0)),
+            Box::new(Cutout::new(interface, 1.0)),
+        ];
+        for material in wrapped {
+            assert!(material.is_interface());
+            assert!(material.interior().is_some());
+        }
+    }
+
+    #[test]
+    fn test_grid_volume_hit() {
+        let volume = GridVolume::new(GridMedium::new(
+            Grid::new([1, 1, 1], vec![1.0]),
+            unit_cube(),
+        ));
+        let r = Ray::new(DVec3::new(0.5, 2.0, 0.5), DVec3::NEG_Y);
+        let hr = volume.hit(&r, Interval::new(1e-3, f64::INFINITY)).unwrap();
+        assert_eq!((hr.t, hr.normal, hr.front_face), (1.0, DVec3::Y, true));
+        let inside = hr.ray(r.direction);
+        let hr = volume
+            .hit(&inside, Interval::new(1e-3, f64::INFINITY))
+            .unwrap();
+        assert_eq!((hr.t, hr.normal, hr.front_face), (1.0, DVec3::Y, false));
+    }
+}
@@ -0,0 +1,330 @@
+use std::{f64::consts::PI, sync::Arc};
+
+use glam::DVec3;
+use raytracer::{
+    cutout::Cutout,
+    environment::EnvironmentMap,
+    light::{AreaLight, Point},
+    material::{DiffuseLight, Lambertian},
+    medium::{Haze, HeightFog, Homogeneous},
+    spectrum::Wavelengths,
+    subsurface::Subsurface,
+    texture::ImageTexture,
+    volume::ConstantMedium,
+    Config, Hit, HitRecord, Interval, Ray, Scene, Sphere, AABB, BVH,
+};
+
+fn spheres() -> BVH {
+    let material = Arc::new(Lambertian::new().albedo(DVec3::splat(0.5)));
+    BVH::new((0..10).map(|i| {
+        let center = DVec3::new(3.0 * i as f64, 0.0, 0.0);
+        Box::new(Sphere::new(center, 1.0, material.clone())) as Box<dyn Hit>
+    }))
+}
+
+#[test]
+fn test_bvh_nearest_hit() {
+    let bvh = spheres();
+    let ray = Ray::new(DVec3::new(-5.0, 0.0, 0.0), DVec3::X);
+    let hr = bvh
+        .hit(&ray, Interval::new(1e-3, f64::INFINITY))
+        .expect("ray hits the first sphere");
+    assert!((hr.t - 4.0).abs() < 1e-9);
+    assert!(hr.front_face);
+    assert_eq!(hr.normal, DVec3::NEG_X);
+
+    let ray = Ray::new(DVec3::new(-5.0, 2.0, 0.0), DVec3::X);
+    assert!(bvh.hit(&ray, Interval::new(1e-3, f64::INFINITY)).is_none());
+}
+
+#[test]
+fn test_custom_primitive() {
+    // Primitives outside the crate can be traced through the BVH
+    struct Floor(Lambertian);
+
+    impl Hit for Floor {
+        fn aabb(&self) -> AABB {
+            AABB::bounding_box([DVec3::new(-1.0, -1e-3, -1.0), DVec3::new(1.0, 0.0, 1.0)])
+        }
+
+        fn clipped_aabb(&self, _axis: DVec3, _t1: f64, _t2: f64) -> AABB {
+            // A conservative bound is enough
+            self.aabb()
+        }
+
+        fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord<'_>> {
+            let t = -r.origin.y / r.direction.y;
+            let p = r.at(t);
+            (ray_t.surrounds(t) && p.x.abs() <= 1.0 && p.z.abs() <= 1.0)
+                .then(|| HitRecord::new(r, p, t, DVec3::Y, &self.0))
+        }
+    }
+
+    let bvh = BVH::new([Box::new(Floor(Lambertian::new())) as Box<dyn Hit>]);
+    let ray = Ray::new(DVec3::new(0.5, 1.0, 0.5), DVec3::NEG_Y);
+    let hr = bvh
+        .hit(&ray, Interval::new(1e-3, f64::INFINITY))
+        .expect("ray hits the floor");
+    assert!((hr.t - 1.0).abs() < 1e-9);
+    assert_eq!(hr.normal, DVec3::Y);
+}
+
+#[test]
+fn test_cutout() {
+    let material = Arc::new(Lambertian::new());
+    let hole = Arc::new(Cutout::new(material.clone(), 0.0));
+    let half = Arc::new(Cutout::new(material.clone(), 0.5));
+    let bvh = BVH::new([
+        Box::new(Sphere::new(DVec3::ZERO, 1.0, hole)) as Box<dyn Hit>,
+        Box::new(Sphere::new(DVec3::new(3.0, 0.0, 0.0), 1.0, half)),
+        Box::new(Sphere::new(DVec3::new(6.0, 0.0, 0.0), 1.0, material)),
+    ]);
+    let n = 2000;
+    let mut hits = [0; 3];
+    for _ in 0..n {
+        let ray = Ray::new(DVec3::new(-5.0, 0.0, 0.0), DVec3::X);
+        let hr = bvh
+            .hit(&ray, Interval::new(1e-3, f64::INFINITY))
+            .expect("ray hits the opaque sphere");
+        hits[(hr.p.x / 3.0).round() as usize] += 1;
+        // Testing the same ray again, as when a primitive is in several
+        // leaves, makes the same choices
+        let again = bvh.hit(&ray, Interval::new(1e-3, f64::INFINITY)).unwrap();
+        assert_eq!(again.t, hr.t);
+    }
+    // Both sides of the half-transparent sphere are hit in turn
+    assert_eq!(hits[0], 0);
+    assert!((hits[1] as f64 / n as f64 - 0.75).abs() < 0.05, "{hits:?}");
+    assert!((hits[2] as f64 / n as f64 - 0.25).abs() < 0.05, "{hits:?}");
+}
+
+#[test]
+fn test_constant_medium() {
+    // Light through a black smoke sphere is attenuated by Beer-Lambert
+    let smoke = ConstantMedium::new(
+        Box::new(Sphere::new(DVec3::ZERO, 1.0, Arc::new(Lambertian::new()))),
+        0.5,
+        DVec3::ZERO,
+    );
+    let scene = Scene::new(BVH::new([Box::new(smoke) as Box<dyn Hit>]));
+    let camera = Config::new().camera();
+    let ray = Ray::new(DVec3::new(-5.0, 0.0, 0.0), DVec3::X);
+    let sky = camera.ray_colour(&Scene::new(BVH::new([])), &ray, 10);
+    let n = 4000;
+    let total: DVec3 = (0..n).map(|_| camera.ray_colour(&scene, &ray, 10)).sum();
+    let expected = sky * (-1.0f64).exp();
+    assert!(
+        (total / n as f64).abs_diff_eq(expected, 0.03),
+        "{total} {expected}"
+    );
+}
+
+#[test]
+fn test_subsurface_furnace() {
+    // Under uniform white light, a translucent sphere that absorbs nothing
+    // looks as bright as its surroundings, as all light entering it leaves
+    let sky = || EnvironmentMap::new(ImageTexture::new(1, 1, vec![DVec3::ONE]));
+    let camera = Config::new().camera();
+    let ray = Ray::new(DVec3::new(-5.0, 0.3, 0.0), DVec3::X);
+    let n = 2000;
+    for (albedo, range) in [(1.0, 0.98..1.02), (0.9, 0.6..0.95)] {
+        let medium = Homogeneous::from_albedo(DVec3::splat(albedo), DVec3::splat(1.0));
+        let material = Subsurface::new().ior(1.2).medium(medium);
+        let sphere = Sphere::new(DVec3::ZERO, 1.0, Arc::new(material));
+        let scene = Scene::new(BVH::new([Box::new(sphere) as Box<dyn Hit>])).light(sky());
+        let total: DVec3 = (0..n).map(|_| camera.ray_colour(&scene, &ray, 50)).sum();
+        let mean = total / n as f64;
+        assert!(range.contains(&mean.x), "albedo {albedo}: {mean}");
+    }
+}
+
+#[test]
+fn test_atmosphere() {
+    // Fog and haze absorb light on the way to the background too
+    let fog = HeightFog::new()
+        .density(0.2)
+        .falloff(0.5)
+        .albedo(DVec3::ZERO);
+    let haze = Haze::new().density(0.2).ceiling(2.0).albedo(DVec3::ZERO);
+    let camera = Config::new().camera();
+    let ray = Ray::new(DVec3::ZERO, DVec3::Y);
+    let n = 4000;
+    for (scene, depth) in [
+        (Scene::new(BVH::new([])).atmosphere(fog), 0.2f64 / 0.5),
+        (Scene::new(BVH::new([])).atmosphere(haze), 0.2 * 2.0),
+    ] {
+        let total: DVec3 = (0..n).map(|_| camera.ray_colour(&scene, &ray, 10)).sum();
+        let expected = DVec3::new(0.5, 0.7, 1.0) * (-depth).exp();
+        assert!(
+            (total / n as f64).abs_diff_eq(expected, 0.03),
+            "{total} {expected}"
+        );
+    }
+}
+
+/// Infinite horizontal plane through the origin.
+struct Plane(Lambertian);
+
+impl Hit for Plane {
+    fn aabb(&self) -> AABB {
+        AABB::bounding_box([DVec3::new(-1e6, -1e-3, -1e6), DVec3::new(1e6, 0.0, 1e6)])
+    }
+
+    fn clipped_aabb(&self, _axis: DVec3, _t1: f64, _t2: f64) -> AABB {
+        self.aabb()
+    }
+
+    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord<'_>> {
+        let t = -r.origin.y / r.direction.y;
+        ray_t
+            .surrounds(t)
+            .then(|| HitRecord::new(r, r.at(t), t, DVec3::Y, &self.0))
+    }
+}
+#[test]
+fn test_direct_lighting() {
+    // A diffuse plane lit by the default gradient sky, which is reached both
+    // by light sampling and by scattering
+    let plane = Plane(Lambertian::new().albedo(DVec3::splat(0.5)));
+    let scene = Scene::new(BVH::new([Box::new(plane) as Box<dyn Hit>]));
+    let camera = Config::new().camera();
+    let ray = Ray::new(DVec3::new(0.0, 1.0, 0.0), DVec3::new(0.3, -1.0, 0.0));
+    let n = 20_000;
+    let total: DVec3 = (0..n).map(|_| camera.ray_colour(&scene, &ray, 10)).sum();
+    // Cosine-weighted integral of the gradient over the upper hemisphere
+    let expected = 0.5 * (DVec3::ONE + 5.0 * DVec3::new(0.5, 0.7, 1.0)) / 6.0;
+    assert!(
+        (total / n as f64).abs_diff_eq(expected, 0.01),
+        "{total} {expected}"
+    );
+}
+
+#[test]
+fn test_delta_lights() {
+    let plane = Plane(Lambertian::new().albedo(DVec3::splat(0.5)));
+    let blocker = Sphere::new(DVec3::new(2.0, 1.0, 0.0), 0.5, Arc::new(Lambertian::new()));
+    let scene = Scene::new(BVH::new([
+        Box::new(plane) as Box<dyn Hit>,
+        Box::new(blocker),
+    ]))
+    .light(
+        Point::new()
+            .position(DVec3::new(0.0, 2.0, 0.0))
+            .intensity(4.0),
+    )
+    .light(Point::new().position(DVec3::new(4.0, 2.0, 0.0)));
+    let camera = Config::new().camera();
+    // Only direct light, which the point above gives to a diffuse surface
+    // as an irradiance of one. The other point is behind the sphere.
+    let ray = Ray::new(DVec3::new(0.0, 1.0, 1.0), DVec3::new(0.0, -1.0, -1.0));
+    let n = 20_000;
+    let total: DVec3 = (0..n).map(|_| camera.ray_colour(&scene, &ray, 1)).sum();
+    let expected = DVec3::splat(0.5 / PI);
+    assert!(
+        (total / n as f64).abs_diff_eq(expected, 0.01),
+        "{total} {expected}"
+    );
+}
+
+#[test]
+fn test_spectral_reflection() {
+    // Spectra of the surface colour and the light multiply at the sampled
+    // wavelengths and return close to the surface colour under white light
+    let albedo = DVec3::new(0.8, 0.3, 0.1);
+    let plane = Plane(Lambertian::new().albedo(albedo));
+    let scene = Scene::new(BVH::new([Box::new(plane) as Box<dyn Hit>])).light(
+        Point::new()
+            .position(DVec3::new(0.0, 2.0, 0.0))
+            .intensity(4.0),
+    );
+    let camera = Config::new().spectral(true).camera();
+    let ray = Ray::new(DVec3::new(0.0, 1.0, 1.0), DVec3::new(0.0, -1.0, -1.0));
+    let n = 20_000;
+    let total: DVec3 = (0..n)
+        .map(|_| {
+            let ray = ray.with_wavelengths(Some(Wavelengths::sample()));
+            camera.ray_colour(&scene, &ray, 1)
+        })
+        .sum();
+    let expected = albedo / PI;
+    assert!(
+        (total / n as f64).abs_diff_eq(expected, 0.01),
+        "{total} {expected}"
+    );
+}
+
+#[test]
+fn test_emissive_spheres() {
+    // A diffuse plane under a glowing sphere, with more under the plane
+    let plane = Plane(Lambertian::new().albedo(DVec3::splat(0.5)));
+    let glow = Arc::new(DiffuseLight::new());
+    let lamp = Sphere::new(DVec3::new(0.0, 3.0, 0.0), 1.0, glow.clone());
+    let hidden = (0..100).map(|i| {
+        let center = DVec3::new(i as f64 - 50.0, -5.0, 0.0);
+        Sphere::new(center, 0.5, glow.clone())
+    });
+    let mut objects = vec![Box::new(plane) as Box<dyn Hit>, Box::new(lamp.clone())];
+    objects.extend(hidden.clone().map(|s| Box::new(s) as Box<dyn Hit>));
+    let scene = hidden.fold(Scene::new(BVH::new(objects)).light(lamp), Scene::light);
+    let camera = Config::new().camera();
+    // Reflected light from both light sampling and scattered rays that reach
+    // the sphere, which fills a cone of half-angle asin(1/3)
+    let ray = Ray::new(DVec3::new(0.0, 1.0, 1.0), DVec3::new(0.0, -1.0, -1.0));
+    let n = 20_000;
+    let total: DVec3 = (0..n).map(|_| camera.ray_colour(&scene, &ray, 2)).sum();
+    let expected = DVec3::splat(0.5 / 9.0);
+    assert!(
+        (total / n as f64).abs_diff_eq(expected, 0.005),
+        "{total} {expected}"
+    );
+}
+
+#[test]
+fn test_area_light() {
+    // A small glowing sphere lights the plane like a point of the same power
+    let plane = Plane(Lambertian::new().albedo(DVec3::splat(0.5)));
+    let sphere = Sphere::new(DVec3::new(0.0, 3.0, 0.0), 0.5, Arc::new(Lambertian::new()));
+    let lamp = AreaLight::new(sphere, DiffuseLight::new()).power(36.0 * PI);
+    let scene = Scene::new(BVH::new([
+        Box::new(plane) as Box<dyn Hit>,
+        Box::new(lamp.clone()),
+    ]))
+    .light(lamp);
+    let camera = Config::new().camera();
+    let ray = Ray::new(DVec3::new(0.0, 1.0, 1.0), DVec3::new(0.0, -1.0, -1.0));
+    let n = 20_000;
+    let total: DVec3 = (0..n).map(|_| camera.ray_colour(&scene, &ray, 2)).sum();
+    let expected = DVec3::splat(0.5 / PI);
+    assert!(
+        (total / n as f64).abs_diff_eq(expected, 0.005),
+        "{total} {expected}"
+    );
+}
+
+#[test]
+fn test_area_light_through_interface() {
+    // A large lamp just above a plane, wrapped in a clear fog whose boundary
+    // scattered rays pass through before reaching the lamp. The lamp fills
+    // a cone of half-angle asin(0.8) seen from the origin.
+    let plane = Plane(Lambertian::new().albedo(DVec3::splat(0.5)));
+    let center = DVec3::new(0.0, 2.5, 0.0);
+    let sphere = Sphere::new(center, 2.0, Arc::new(Lambertian::new()));
+    let lamp = AreaLight::new(sphere, DiffuseLight::new());
+    let boundary = Sphere::new(center, 2.05, Arc::new(Lambertian::new()));
+    let fog = ConstantMedium::new(Box::new(boundary), 0.0, DVec3::ONE);
+    let scene = Scene::new(BVH::new([
+        Box::new(plane) as Box<dyn Hit>,
+        Box::new(lamp.clone()),
+        Box::new(fog),
+    ]))
+    .light(lamp);
+    let camera = Config::new().camera();
+    let ray = Ray::new(DVec3::new(3.0, 0.5, 0.0), DVec3::new(-3.0, -0.5, 0.0));
+    let n = 20_000;
+    let total: DVec3 = (0..n).map(|_| camera.ray_colour(&scene, &ray, 2)).sum();
+    let expected = DVec3::splat(0.5 * 0.64);
+    assert!(
+        (total / n as f64).abs_diff_eq(expected, 0.01),
+        "{total} {expected}"
+    );
+}